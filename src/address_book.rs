@@ -0,0 +1,117 @@
+//! Persists the addresses of discovered peers so that after a restart the
+//! observer can reconnect to the network without waiting for DHT discovery.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    events::{EventKind, ObservedEvent},
+    metrics,
+};
+
+/// Only the most recently seen addresses of each peer are kept
+const MAX_ADDRS_PER_PEER: usize = 8;
+/// Peers that haven't been seen for this long are dropped from the file
+const MAX_PEER_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Entry {
+    last_seen: i64,
+    addrs: Vec<String>,
+}
+
+pub struct AddressBook {
+    path: PathBuf,
+    peers: HashMap<String, Entry>,
+    dirty: bool,
+}
+
+impl AddressBook {
+    /// Reads the address book, starting from an empty one if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let peers = match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Invalid address book {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            peers,
+            dirty: false,
+        })
+    }
+
+    /// Returns the stored addresses, skipping entries that can't be parsed
+    pub fn addresses(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.peers
+            .iter()
+            .filter_map(|(peer_id, entry)| {
+                let peer_id = peer_id.parse().ok()?;
+                let addrs = entry.addrs.iter().filter_map(|a| a.parse().ok()).collect();
+                Some((peer_id, addrs))
+            })
+            .collect()
+    }
+
+    fn add(&mut self, peer_id: &str, address: &str) {
+        let entry = self.peers.entry(peer_id.to_owned()).or_default();
+        entry.last_seen = metrics::now();
+        entry.addrs.retain(|a| a != address);
+        entry.addrs.push(address.to_owned());
+        if entry.addrs.len() > MAX_ADDRS_PER_PEER {
+            entry.addrs.remove(0);
+        }
+        self.dirty = true;
+    }
+
+    fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let cutoff = metrics::now() - MAX_PEER_AGE.as_secs() as i64;
+        self.peers.retain(|_, entry| entry.last_seen >= cutoff);
+
+        // Write to a temporary file first so that a crash never leaves a truncated book behind
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&self.peers)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Records the addresses of seen peers and periodically writes them to disk
+    pub async fn run(mut self, mut events: broadcast::Receiver<ObservedEvent>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(ObservedEvent { kind: EventKind::PeerSeen { peer_id, address }, .. }) => {
+                        self.add(&peer_id, &address);
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(n)) => {
+                        log::warn!("Address book is lagging behind, {n} events dropped");
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = interval.tick() => {
+                    if let Err(e) = self.save() {
+                        log::warn!("Failed to save the address book: {e:?}");
+                    }
+                }
+            }
+        }
+        if let Err(e) = self.save() {
+            log::warn!("Failed to save the address book: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,620 @@
+//! Evaluates alert rules against the observer state and sends notifications
+//! when an alert starts firing or gets resolved.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, watch,
+};
+
+use crate::{
+    cli::{AlertArgs, RuntimeConfig},
+    events::{EventKind, ObservedEvent},
+    metrics::now,
+    state::{State, WorkerInfo},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct RulesFile {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    /// Workers the rule applies to. Empty means all workers.
+    #[serde(default)]
+    pub peer_ids: Vec<String>,
+    #[serde(flatten)]
+    pub condition: Condition,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    /// No successful ping from the worker for `for_sec` seconds
+    NoPing { for_sec: u64 },
+    /// The fraction of failed pings over the last `window_sec` seconds exceeds `threshold`
+    PingErrorRate {
+        threshold: f64,
+        window_sec: u64,
+        #[serde(default = "default_min_pings")]
+        min_pings: usize,
+    },
+}
+
+/// Number of notifications waiting for delivery before new ones are dropped
+const NOTIFICATION_QUEUE_CAPACITY: usize = 256;
+
+fn default_min_pings() -> usize {
+    10
+}
+
+impl Rule {
+    fn applies_to(&self, peer_id: &str) -> bool {
+        self.peer_ids.is_empty() || self.peer_ids.iter().any(|p| p == peer_id)
+    }
+}
+
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read alert rules from {}", path.display()))?;
+    let file: RulesFile = toml::from_str(&contents)
+        .with_context(|| format!("Couldn't parse alert rules from {}", path.display()))?;
+    Ok(file.rules)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub peer_id: String,
+    pub status: AlertStatus,
+    pub summary: String,
+    pub value: f64,
+    pub started_at: i64,
+    pub resolved_at: Option<i64>,
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, alert: &Alert) -> Result<()>;
+}
+
+/// POSTs alerts as JSON to a webhook URL
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(client: reqwest::Client, url: String) -> Self {
+        Self { client, url }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(alert)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Creates the notifiers configured on the command line
+pub fn notifiers(client: &reqwest::Client, args: &AlertArgs) -> Vec<Box<dyn Notifier>> {
+    let template = Arc::new(MessageTemplate::new(
+        args.alert_message_template.clone(),
+        args.observer_url.clone(),
+    ));
+    let mut notifiers: Vec<Box<dyn Notifier>> = args
+        .alert_webhook_urls
+        .iter()
+        .map(|url| Box::new(WebhookNotifier::new(client.clone(), url.clone())) as _)
+        .collect();
+    if let (Some(token), Some(channel)) = (&args.alert_slack_token, &args.alert_slack_channel) {
+        notifiers.push(Box::new(SlackNotifier::new(
+            client.clone(),
+            token.clone(),
+            channel.clone(),
+            template.clone(),
+        )));
+    }
+    if let (Some(token), Some(chat_id)) =
+        (&args.alert_telegram_bot_token, &args.alert_telegram_chat_id)
+    {
+        notifiers.push(Box::new(TelegramNotifier::new(
+            client.clone(),
+            token.clone(),
+            chat_id.clone(),
+            template,
+        )));
+    }
+    notifiers
+}
+
+/// Renders a notification message, replacing `{rule}`, `{peer_id}`, `{status}`, `{summary}`,
+/// `{value}`, `{started_at}` and `{worker_url}` placeholders
+pub struct MessageTemplate {
+    template: String,
+    observer_url: Option<String>,
+}
+
+impl MessageTemplate {
+    pub fn new(template: String, observer_url: Option<String>) -> Self {
+        Self {
+            // Allow line breaks to be given as `\n` in the command line and the environment
+            template: template.replace("\\n", "\n"),
+            observer_url: observer_url.map(|url| url.trim_end_matches('/').to_owned()),
+        }
+    }
+
+    pub fn render(&self, alert: &Alert) -> String {
+        let status = match alert.status {
+            AlertStatus::Firing => "FIRING",
+            AlertStatus::Resolved => "RESOLVED",
+        };
+        let worker_url = self
+            .observer_url
+            .as_ref()
+            .map(|url| format!("{url}/workers/{}/uptime", alert.peer_id))
+            .unwrap_or_default();
+        self.template
+            .replace("{rule}", &alert.rule)
+            .replace("{peer_id}", &alert.peer_id)
+            .replace("{status}", status)
+            .replace("{summary}", &alert.summary)
+            .replace("{value}", &format!("{:.3}", alert.value))
+            .replace("{started_at}", &alert.started_at.to_string())
+            .replace("{worker_url}", &worker_url)
+            .trim_end()
+            .to_owned()
+    }
+}
+
+/// Posts alerts to a Slack channel using a bot token
+pub struct SlackNotifier {
+    client: reqwest::Client,
+    token: String,
+    channel: String,
+    template: Arc<MessageTemplate>,
+}
+
+impl SlackNotifier {
+    pub fn new(
+        client: reqwest::Client,
+        token: String,
+        channel: String,
+        template: Arc<MessageTemplate>,
+    ) -> Self {
+        Self {
+            client,
+            token,
+            channel,
+            template,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SlackResponse {
+    ok: bool,
+    error: Option<String>,
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let response: SlackResponse = self
+            .client
+            .post("https://slack.com/api/chat.postMessage")
+            .bearer_auth(&self.token)
+            .json(&serde_json::json!({
+                "channel": self.channel,
+                "text": self.template.render(alert),
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if !response.ok {
+            anyhow::bail!("Slack API error: {}", response.error.unwrap_or_default());
+        }
+        Ok(())
+    }
+}
+
+/// Sends alerts to a Telegram chat through the Bot API
+pub struct TelegramNotifier {
+    client: reqwest::Client,
+    bot_token: String,
+    chat_id: String,
+    template: Arc<MessageTemplate>,
+}
+
+impl TelegramNotifier {
+    pub fn new(
+        client: reqwest::Client,
+        bot_token: String,
+        chat_id: String,
+        template: Arc<MessageTemplate>,
+    ) -> Self {
+        Self {
+            client,
+            bot_token,
+            chat_id,
+            template,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TelegramResponse {
+    ok: bool,
+    description: Option<String>,
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+        // The token is part of the URL, so it must not end up in error messages
+        let response: TelegramResponse = self
+            .client
+            .post(url)
+            .json(&serde_json::json!({
+                "chat_id": self.chat_id,
+                "text": self.template.render(alert),
+                "disable_web_page_preview": true,
+            }))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Telegram request failed: {}", e.without_url()))?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid Telegram response: {}", e.without_url()))?;
+        if !response.ok {
+            anyhow::bail!(
+                "Telegram API error: {}",
+                response.description.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
+
+pub struct AlertEngine {
+    rules: Arc<Vec<Rule>>,
+    config: watch::Receiver<RuntimeConfig>,
+    /// Queue of the delivery task, so slow notifiers don't delay the evaluation
+    notifications: mpsc::Sender<Alert>,
+    state: Arc<State>,
+    /// Outcomes of recent pings per worker, used for error rates
+    ping_history: HashMap<String, VecDeque<(i64, bool)>>,
+    firing: HashMap<(String, String), Alert>,
+}
+
+impl AlertEngine {
+    pub fn new(
+        mut config: watch::Receiver<RuntimeConfig>,
+        notifiers: Vec<Box<dyn Notifier>>,
+        state: Arc<State>,
+    ) -> Self {
+        let (notifications, queue) = mpsc::channel(NOTIFICATION_QUEUE_CAPACITY);
+        tokio::spawn(deliver(notifiers, queue));
+        Self {
+            rules: config.borrow_and_update().alert_rules.clone(),
+            config,
+            notifications,
+            state,
+            ping_history: Default::default(),
+            firing: Default::default(),
+        }
+    }
+
+    pub async fn run(
+        mut self,
+        mut events: broadcast::Receiver<ObservedEvent>,
+        evaluation_interval: Duration,
+    ) {
+        let mut interval = tokio::time::interval(evaluation_interval);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => self.record(&event),
+                    Err(RecvError::Lagged(n)) => {
+                        log::warn!("Alert engine is lagging behind, {n} events dropped");
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = interval.tick() => self.evaluate(),
+                // Alerts of removed rules get resolved on the next evaluation
+                Ok(()) = self.config.changed() => {
+                    let rules = self.config.borrow_and_update().alert_rules.clone();
+                    if rules != self.rules {
+                        log::info!("Reloaded {} alert rules", rules.len());
+                        self.rules = rules;
+                    }
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, event: &ObservedEvent) {
+        if let EventKind::Ping {
+            peer_id, rtt_secs, ..
+        } = &event.kind
+        {
+            self.ping_history
+                .entry(peer_id.clone())
+                .or_default()
+                .push_back((event.timestamp, rtt_secs.is_some()));
+        }
+    }
+
+    fn evaluate(&mut self) {
+        let now = now();
+        self.prune_history(now);
+
+        // Gateways, boot nodes and other peers aren't pinged, so rules only apply to workers
+        let workers: Vec<_> = self
+            .state
+            .workers()
+            .into_iter()
+            .filter(WorkerInfo::is_worker)
+            .collect();
+        let mut active = HashMap::new();
+        for rule in &self.rules {
+            for worker in workers.iter().filter(|w| rule.applies_to(&w.peer_id)) {
+                let result = match &rule.condition {
+                    Condition::NoPing { for_sec } => {
+                        // Workers that were never pinged are measured from when they were first seen
+                        let last = worker.last_ping.or(worker.last_seen).unwrap_or(now);
+                        let silence = now - last;
+                        (silence >= *for_sec as i64).then(|| {
+                            (
+                                silence as f64,
+                                format!(
+                                    "No successful ping from {} for {silence}s",
+                                    worker.peer_id
+                                ),
+                            )
+                        })
+                    }
+                    Condition::PingErrorRate {
+                        threshold,
+                        window_sec,
+                        min_pings,
+                    } => self
+                        .ping_history
+                        .get(&worker.peer_id)
+                        .and_then(|history| {
+                            error_rate(history, now - *window_sec as i64, *min_pings)
+                        })
+                        .filter(|rate| rate > threshold)
+                        .map(|rate| {
+                            (
+                                rate,
+                                format!(
+                                    "{:.1}% of pings to {} failed over the last {window_sec}s",
+                                    rate * 100.0,
+                                    worker.peer_id
+                                ),
+                            )
+                        }),
+                };
+                if let Some((value, summary)) = result {
+                    active.insert(
+                        (rule.name.clone(), worker.peer_id.clone()),
+                        (value, summary),
+                    );
+                }
+            }
+        }
+
+        let mut notifications = Vec::new();
+        self.firing.retain(|key, alert| {
+            if active.contains_key(key) {
+                return true;
+            }
+            alert.status = AlertStatus::Resolved;
+            alert.resolved_at = Some(now);
+            notifications.push(alert.clone());
+            false
+        });
+        for (key, (value, summary)) in active {
+            match self.firing.get_mut(&key) {
+                Some(alert) => {
+                    alert.value = value;
+                    alert.summary = summary;
+                }
+                None => {
+                    let alert = Alert {
+                        rule: key.0.clone(),
+                        peer_id: key.1.clone(),
+                        status: AlertStatus::Firing,
+                        summary,
+                        value,
+                        started_at: now,
+                        resolved_at: None,
+                    };
+                    notifications.push(alert.clone());
+                    self.firing.insert(key, alert);
+                }
+            }
+        }
+
+        for alert in notifications {
+            log::info!(
+                "Alert {} for {} is {:?}: {}",
+                alert.rule,
+                alert.peer_id,
+                alert.status,
+                alert.summary
+            );
+            if self.notifications.try_send(alert).is_err() {
+                log::warn!("Alert notification queue is full, notification dropped");
+            }
+        }
+    }
+
+    fn prune_history(&mut self, now: i64) {
+        let retention = self
+            .rules
+            .iter()
+            .filter_map(|rule| match rule.condition {
+                Condition::PingErrorRate { window_sec, .. } => Some(window_sec as i64),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        self.ping_history.retain(|_, history| {
+            while history.front().is_some_and(|(t, _)| *t < now - retention) {
+                history.pop_front();
+            }
+            !history.is_empty()
+        });
+    }
+}
+
+/// Returns the fraction of failed pings since the given time, if there were at least `min_pings`
+fn error_rate(history: &VecDeque<(i64, bool)>, since: i64, min_pings: usize) -> Option<f64> {
+    let recent = history.iter().filter(|(t, _)| *t >= since);
+    let (total, failed) = recent.fold((0, 0), |(total, failed), (_, ok)| {
+        (total + 1, failed + usize::from(!ok))
+    });
+    (total > 0 && total >= min_pings).then(|| failed as f64 / total as f64)
+}
+
+/// Sends queued alerts to every notifier in order
+async fn deliver(notifiers: Vec<Box<dyn Notifier>>, mut queue: mpsc::Receiver<Alert>) {
+    while let Some(alert) = queue.recv().await {
+        for notifier in &notifiers {
+            if let Err(e) = notifier.notify(&alert).await {
+                log::warn!("Failed to send alert notification: {e:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_rate_needs_min_pings() {
+        let history: VecDeque<_> = [(10, false), (20, true), (30, false), (40, true)].into();
+        assert_eq!(error_rate(&history, 0, 4), Some(0.5));
+        assert_eq!(error_rate(&history, 0, 5), None);
+        // Only pings since the start of the window count
+        assert_eq!(error_rate(&history, 25, 2), Some(0.5));
+        assert_eq!(error_rate(&history, 25, 3), None);
+    }
+
+    #[test]
+    fn error_rate_without_pings() {
+        assert_eq!(error_rate(&VecDeque::new(), 0, 0), None);
+    }
+
+    #[tokio::test]
+    async fn no_ping_only_fires_for_workers() {
+        let args = crate::testing::default_args();
+        let state = Arc::new(crate::new_state(&args));
+        let long_ago = now() - 3600;
+        state.peer_seen("gateway", "/ip4/10.0.0.1/udp/1/quic-v1", long_ago);
+        state.peer_identified("gateway", "sqd-gateway/1.0.0");
+        state.peer_seen("worker", "/ip4/10.0.0.2/udp/1/quic-v1", long_ago);
+        state.peer_identified("worker", "sqd-worker/2.1.0");
+        let (_config_tx, config_rx) = watch::channel(args.runtime_config().unwrap());
+        let mut engine = AlertEngine::new(config_rx, Vec::new(), state);
+        engine.rules = Arc::new(vec![Rule {
+            name: "silent".to_owned(),
+            peer_ids: Vec::new(),
+            condition: Condition::NoPing { for_sec: 300 },
+        }]);
+
+        engine.evaluate();
+
+        let firing: Vec<_> = engine
+            .firing
+            .keys()
+            .map(|(_, peer)| peer.as_str())
+            .collect();
+        assert_eq!(firing, ["worker"]);
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let file: RulesFile = toml::from_str(
+            r#"
+            [[rule]]
+            name = "silent"
+            kind = "no_ping"
+            for_sec = 300
+
+            [[rule]]
+            name = "flaky"
+            kind = "ping_error_rate"
+            peer_ids = ["a"]
+            threshold = 0.5
+            window_sec = 600
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.rules[0].condition, Condition::NoPing { for_sec: 300 });
+        assert_eq!(
+            file.rules[1].condition,
+            Condition::PingErrorRate {
+                threshold: 0.5,
+                window_sec: 600,
+                min_pings: default_min_pings(),
+            }
+        );
+        assert!(file.rules[0].applies_to("b"));
+        assert!(file.rules[1].applies_to("a"));
+        assert!(!file.rules[1].applies_to("b"));
+    }
+
+    #[test]
+    fn message_template_is_rendered() {
+        let template = MessageTemplate::new(
+            "{status} {rule}\\n{summary} ({value}) {worker_url}".to_owned(),
+            Some("https://observer/".to_owned()),
+        );
+        let alert = Alert {
+            rule: "silent".to_owned(),
+            peer_id: "a".to_owned(),
+            status: AlertStatus::Firing,
+            summary: "No ping".to_owned(),
+            value: 300.0,
+            started_at: 0,
+            resolved_at: None,
+        };
+        assert_eq!(
+            template.render(&alert),
+            "FIRING silent\nNo ping (300.000) https://observer/workers/a/uptime"
+        );
+    }
+}
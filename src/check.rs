@@ -0,0 +1,84 @@
+//! Validates the configuration and the resources the observer depends on without starting it.
+
+use anyhow::{ensure, Result};
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::{address_book::AddressBook, cli::Cli, contract, geoip::GeoIp};
+
+/// Runs every applicable check, printing one line per check, and fails if any of them failed
+pub async fn run(args: &Cli) -> Result<()> {
+    let mut failed = 0;
+    let mut report = |name: &str, result: Option<Result<String>>| match result {
+        None => println!("skip {name}: not configured"),
+        Some(Ok(details)) => println!("ok   {name}: {details}"),
+        Some(Err(e)) => {
+            failed += 1;
+            println!("FAIL {name}: {e:#}");
+        }
+    };
+
+    report("identity", check_identity(args).await);
+    report("http", Some(check_http(args).await));
+    report("tls", check_tls(args).await);
+    report("rpc", check_rpc(args).await);
+    report(
+        "geoip",
+        (args.geoip_country_db.is_some() || args.geoip_asn_db.is_some()).then(|| {
+            GeoIp::open(
+                args.geoip_country_db.as_deref(),
+                args.geoip_asn_db.as_deref(),
+            )
+            .map(|_| "databases opened".to_owned())
+        }),
+    );
+    report(
+        "address book",
+        args.address_book.as_deref().map(|path| {
+            AddressBook::load(path).map(|book| format!("{} peers", book.addresses().len()))
+        }),
+    );
+
+    ensure!(failed == 0, "{failed} checks failed");
+    Ok(())
+}
+
+async fn check_identity(args: &Cli) -> Option<Result<String>> {
+    let path = args.key.clone()?;
+    Some(
+        async {
+            // A missing key would otherwise be generated
+            ensure!(path.is_file(), "{} doesn't exist", path.display());
+            let keypair = sqd_network_transport::util::get_keypair(Some(path)).await?;
+            Ok(format!("peer ID {}", keypair.public().to_peer_id()))
+        }
+        .await,
+    )
+}
+
+async fn check_http(args: &Cli) -> Result<String> {
+    let addr = args.http_addr();
+    tokio::net::TcpListener::bind(addr).await?;
+    Ok(format!("{addr} is available"))
+}
+
+async fn check_tls(args: &Cli) -> Option<Result<String>> {
+    let tls = args.tls()?;
+    Some(
+        RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+            .await
+            .map(|_| "certificate loaded".to_owned())
+            .map_err(Into::into),
+    )
+}
+
+async fn check_rpc(args: &Cli) -> Option<Result<String>> {
+    let (rpc_url, l1_rpc_url) = (args.rpc_url.clone()?, args.l1_rpc_url.clone()?);
+    Some(
+        async {
+            let client = contract::connect(rpc_url, l1_rpc_url, args.network).await?;
+            let epoch = contract::current_epoch(client.as_ref()).await?;
+            Ok(format!("current epoch {epoch}"))
+        }
+        .await,
+    )
+}
@@ -1,20 +1,78 @@
-use clap::Parser;
-use libp2p::Multiaddr;
-use std::path::PathBuf;
+use anyhow::{anyhow, Context};
+use clap::{parser::ValueSource, ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
+use libp2p::{Multiaddr, PeerId};
+use std::{
+    ffi::OsString,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use sqd_contract_client::Network;
+
+use crate::{
+    alerts::{load_rules, Rule},
+    score::ScoreWeights,
+};
 use sqd_network_transport::BootNode;
 
 #[derive(Parser)]
-#[command()]
+#[command(subcommand_negates_reqs = true)]
 pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to a TOML config file. Values given on the command line or in the environment take precedence
+    #[arg(long, env)]
+    pub config: Option<PathBuf>,
+
+    /// Log filter directives in env_logger syntax
+    #[arg(long, env = "RUST_LOG", default_value = "info")]
+    pub log_filter: String,
+
+    /// Log output format
+    #[arg(long, env, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
     /// HTTP port to listen on
-    #[arg(short, long, default_value_t = 8000)]
+    #[arg(short, long, env, default_value_t = 8000)]
     pub(crate) port: u16,
 
+    /// Full socket address of the HTTP server, e.g. 127.0.0.1:8000 or [::1]:8000. Overrides --port
+    #[arg(long, env)]
+    pub http_addr: Option<SocketAddr>,
+
+    /// PEM certificate chain to serve the HTTP API over HTTPS. Reloaded on SIGHUP
+    #[arg(long, env, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key matching --tls-cert
+    #[arg(long, env, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Require `Authorization: Bearer <token>` on all endpoints except /healthz and /readyz
+    #[arg(long, env, hide_env_values = true)]
+    pub http_auth_token: Option<String>,
+
+    /// Only accept HTTP requests from these networks, e.g. 10.0.0.0/8. Can be repeated.
+    /// /healthz and /readyz are always accessible
+    #[arg(long, env, value_delimiter = ',')]
+    pub http_allow_cidr: Vec<IpNet>,
+
     /// Path to libp2p key file
-    #[arg(short, long, env = "KEY_PATH")]
-    pub key: PathBuf,
+    #[arg(
+        short,
+        long,
+        env = "KEY_PATH",
+        required_unless_present_any = ["ephemeral_identity", "simulate"]
+    )]
+    pub key: Option<PathBuf>,
+
+    /// Use a freshly generated key instead of a key file, for read-only observation
+    #[arg(long, env, conflicts_with = "key")]
+    pub ephemeral_identity: bool,
 
     /// Addresses on which the p2p node will listen
     #[arg(long, env, value_delimiter = ',')]
@@ -24,6 +82,27 @@ pub(crate) struct Cli {
     #[arg(long, env, value_delimiter = ',')]
     pub p2p_public_addrs: Vec<Multiaddr>,
 
+    /// Peer ID whose events are ignored and that is never dialed (can be repeated)
+    #[arg(long = "block-peer", env = "BLOCK_PEERS", value_delimiter = ',')]
+    pub block_peers: Vec<PeerId>,
+
+    /// If set, only events from these peers are processed and only they are dialed (can be repeated).
+    /// Boot nodes are always allowed.
+    #[arg(long = "allow-peer", env = "ALLOW_PEERS", value_delimiter = ',')]
+    pub allow_peers: Vec<PeerId>,
+
+    /// Maximum number of established connections (unlimited if not set)
+    #[arg(long, env)]
+    pub max_connections: Option<u32>,
+
+    /// Maximum number of established connections to a single peer (unlimited if not set)
+    #[arg(long, env)]
+    pub max_connections_per_peer: Option<u32>,
+
+    /// Maximum number of pending connections in each direction (unlimited if not set)
+    #[arg(long, env)]
+    pub max_pending_connections: Option<u32>,
+
     /// Connect to boot node '<peer_id> <address>'.
     #[arg(
             long,
@@ -36,4 +115,789 @@ pub(crate) struct Cli {
     /// Network to connect to (mainnet or tethys)
     #[arg(long, env, default_value_t = Network::Mainnet)]
     pub network: Network,
+
+    /// Interval between active dial probes of all known peers, in seconds (disabled if not set)
+    #[arg(long, env, value_parser = clap::value_parser!(u64).range(1..))]
+    pub probe_interval_sec: Option<u64>,
+
+    /// Gossipsub topic on which workers broadcast their heartbeats
+    #[arg(long, env, default_value = "/subsquid/worker_heartbeats/1.1.0")]
+    pub heartbeat_topic: String,
+
+    /// Additional gossipsub topics to subscribe to, e.g. for query logs. Their messages are only counted.
+    #[arg(long, env, value_delimiter = ',')]
+    pub gossipsub_topics: Vec<String>,
+
+    /// Maximum number of gossip and identify messages handled from a single peer per minute
+    /// (unlimited if not set). Pings and dial probes made by the observer aren't limited.
+    #[arg(long, env, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_messages_per_peer_per_minute: Option<u32>,
+
+    /// Request-response protocol used by the on-demand status probe, which must match one the workers serve
+    #[arg(long, env, default_value = "/subsquid/worker-status/1.0.0")]
+    pub status_protocol: String,
+
+    /// Time after which an unanswered status request fails, in seconds
+    #[arg(long, env, default_value_t = 20)]
+    pub status_request_timeout_sec: u64,
+
+    /// Interval between DHT random walks used to discover all peers in the network, in seconds
+    #[arg(long, env, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    pub crawl_interval_sec: u64,
+
+    /// Time after which the metrics of a worker that stopped reporting are removed, in seconds
+    #[arg(long, env, default_value_t = 1800, value_parser = clap::value_parser!(u64).range(1..))]
+    pub worker_ttl_sec: u64,
+
+    /// Time without any activity after which a worker is considered silent, in seconds
+    #[arg(long, env, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    pub worker_silence_threshold_sec: u64,
+
+    /// Sentry DSN to report errors and panics to
+    #[arg(long, env, hide_env_values = true)]
+    pub sentry_dsn: Option<String>,
+
+    /// Environment reported to Sentry (defaults to the network name)
+    #[arg(long, env)]
+    pub sentry_environment: Option<String>,
+
+    /// OpenTelemetry collector gRPC endpoint to push metrics to, e.g. http://localhost:4317
+    #[arg(long, env)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Interval between OTLP metric exports, in seconds
+    #[arg(long, env, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    pub otlp_export_interval_sec: u64,
+
+    /// Prometheus remote-write endpoint to push metrics to
+    #[arg(long, env)]
+    pub remote_write_url: Option<String>,
+
+    /// Bearer token sent to the remote-write endpoint
+    #[arg(long, env, hide_env_values = true)]
+    pub remote_write_bearer_token: Option<String>,
+
+    /// Interval between remote-write pushes, in seconds
+    #[arg(long, env, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    pub remote_write_interval_sec: u64,
+
+    /// Periodically write all metrics to this file for node_exporter's textfile collector. Should end with .prom
+    #[arg(long, env)]
+    pub textfile_path: Option<PathBuf>,
+
+    /// Interval between textfile writes, in seconds
+    #[arg(long, env, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    pub textfile_interval_sec: u64,
+
+    /// StatsD server to send counters and gauges to, e.g. localhost:8125. Labels are sent as DogStatsD tags
+    #[arg(long, env)]
+    pub statsd_addr: Option<String>,
+
+    /// Interval between StatsD exports, in seconds
+    #[arg(long, env, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub statsd_interval_sec: u64,
+
+    /// Graphite plaintext endpoint to send per-worker series to, e.g. graphite:2003
+    #[arg(long, env)]
+    pub graphite_addr: Option<String>,
+
+    /// Prefix of all Graphite paths
+    #[arg(long, env, default_value = "observer")]
+    pub graphite_prefix: String,
+
+    /// Interval between Graphite flushes, in seconds
+    #[arg(long, env, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    pub graphite_interval_sec: u64,
+
+    /// Connect timeout of outgoing HTTP requests (ClickHouse, remote write, alert notifications), in seconds
+    #[arg(long, env, default_value_t = 10)]
+    pub http_connect_timeout_sec: u64,
+
+    /// Total timeout of outgoing HTTP requests, in seconds
+    #[arg(long, env, default_value_t = 30)]
+    pub http_timeout_sec: u64,
+
+    /// Maximum number of idle pooled HTTP connections per host
+    #[arg(long, env, default_value_t = 8)]
+    pub http_pool_max_idle_per_host: usize,
+
+    /// Prefix added to the names of all exported metrics, e.g. sqd_observer
+    #[arg(long, env)]
+    pub metrics_prefix: Option<String>,
+
+    /// Constant label added to all exported metrics, as key=value (can be repeated)
+    #[arg(long = "metrics-label", env = "METRICS_LABELS", value_delimiter = ',', value_parser = parse_label)]
+    pub metrics_labels: Vec<(String, String)>,
+
+    /// Maximum time between pings for a worker to be considered continuously online, in seconds
+    #[arg(long, env, default_value_t = 60)]
+    pub uptime_max_gap_sec: u64,
+
+    /// Windows over which worker uptime ratios are reported, in seconds
+    #[arg(long, env, value_delimiter = ',', default_value = "3600,86400,604800")]
+    pub uptime_windows_sec: Vec<u64>,
+
+    /// Windows over which worker ping success ratios are reported, in seconds
+    #[arg(long, env, value_delimiter = ',', default_value = "300,3600,86400")]
+    pub success_ratio_windows_sec: Vec<u64>,
+
+    /// Weight of the uptime ratio over the longest uptime window in the worker score
+    #[arg(long, env, default_value_t = 0.4)]
+    pub score_weight_uptime: f64,
+
+    /// Weight of the ping success ratio over the longest window in the worker score
+    #[arg(long, env, default_value_t = 0.3)]
+    pub score_weight_ping_success: f64,
+
+    /// Weight of the missing chunks in the worker score
+    #[arg(long, env, default_value_t = 0.2)]
+    pub score_weight_missing_chunks: f64,
+
+    /// Weight of the result of the last dial probe in the worker score
+    #[arg(long, env, default_value_t = 0.1)]
+    pub score_weight_reachability: f64,
+
+    /// Number of missing chunks at which the missing chunks part of the score drops to zero
+    #[arg(long, env, default_value_t = 1000)]
+    pub score_missing_chunks_threshold: u64,
+
+    /// Arbitrum RPC endpoint used to read the worker registry (registry polling is disabled if not set)
+    #[arg(long, env, requires = "l1_rpc_url")]
+    pub rpc_url: Option<String>,
+
+    /// Ethereum L1 RPC endpoint required by the contract client
+    #[arg(long, env)]
+    pub l1_rpc_url: Option<String>,
+
+    /// Time without a ping after which a registered worker is reported as silent, in seconds
+    #[arg(long, env, default_value_t = 900)]
+    pub registered_silence_window_sec: u64,
+
+    /// Interval between reads of the worker registry, in seconds
+    #[arg(long, env, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    pub registry_poll_interval_sec: u64,
+
+    /// Path to a MaxMind country database (e.g. GeoLite2-Country.mmdb) used to locate workers
+    #[arg(long, env)]
+    pub geoip_country_db: Option<PathBuf>,
+
+    /// Path to a MaxMind ASN database (e.g. GeoLite2-ASN.mmdb) used to locate workers
+    #[arg(long, env)]
+    pub geoip_asn_db: Option<PathBuf>,
+
+    /// File where the addresses of discovered peers are stored and restored from on startup
+    #[arg(long, env)]
+    pub address_book: Option<PathBuf>,
+
+    /// Interval between writes of the address book to disk, in seconds
+    #[arg(long, env, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    pub address_book_save_interval_sec: u64,
+
+    /// Interval between samples of the per-worker history, in seconds
+    #[arg(long, env, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    pub history_resolution_sec: u64,
+
+    /// Time for which the per-worker history is kept, in seconds
+    #[arg(long, env, default_value_t = 86400)]
+    pub history_retention_sec: u64,
+
+    /// Maximum time since the last network event for the observer to be ready, in seconds
+    #[arg(long, env, default_value_t = 120)]
+    pub readiness_max_event_age_sec: u64,
+
+    /// Maximum time since the last event loop iteration for the observer to be live, in seconds
+    #[arg(long, env, default_value_t = 30)]
+    pub liveness_max_stall_sec: u64,
+
+    /// Report a stalled event stream when no event arrives for this long while boot nodes are connected, in seconds
+    #[arg(long, env, value_parser = clap::value_parser!(u64).range(1..))]
+    pub watchdog_stall_sec: Option<u64>,
+
+    /// Exit with an error when the watchdog detects a stall, so that the supervisor restarts the observer
+    #[arg(long, env, requires = "watchdog_stall_sec")]
+    pub watchdog_exit: bool,
+
+    /// Time given to in-flight HTTP requests and to each stage of flushing the sinks on shutdown, in seconds
+    #[arg(long, env, default_value_t = 10)]
+    pub shutdown_timeout_sec: u64,
+
+    /// Observe the network for --once-duration-sec, print a JSON report and exit,
+    /// with status 2 if the network looks unhealthy
+    #[arg(long, env)]
+    pub once: bool,
+
+    /// How long to observe the network with --once, in seconds
+    #[arg(long, env, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    pub once_duration_sec: u64,
+
+    /// Append every observed event as a JSON line to this file, for the replay command
+    #[arg(long, env)]
+    pub record_events: Option<PathBuf>,
+
+    /// Serve task diagnostics to tokio-console
+    #[cfg(feature = "tokio-console")]
+    #[arg(long, env)]
+    pub tokio_console: bool,
+
+    /// Address of the tokio-console server
+    #[cfg(feature = "tokio-console")]
+    #[arg(long, env, default_value = "127.0.0.1:6669")]
+    pub tokio_console_addr: SocketAddr,
+
+    #[command(flatten)]
+    pub clickhouse: ClickhouseArgs,
+
+    #[command(flatten)]
+    pub alerts: AlertArgs,
+
+    #[cfg(feature = "kafka")]
+    #[command(flatten)]
+    pub kafka: KafkaArgs,
+
+    #[command(flatten)]
+    pub nats: NatsArgs,
+
+    #[command(flatten)]
+    pub simulation: SimulationArgs,
+
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct InfluxArgs {
+    /// InfluxDB v2 URL to push metrics to, e.g. http://localhost:8086
+    #[arg(long, env, requires_all = ["influx_token", "influx_org", "influx_bucket"])]
+    pub influx_url: Option<String>,
+
+    /// API token with write access to the bucket
+    #[arg(long, env, hide_env_values = true)]
+    pub influx_token: Option<String>,
+
+    #[arg(long, env)]
+    pub influx_org: Option<String>,
+
+    #[arg(long, env)]
+    pub influx_bucket: Option<String>,
+
+    /// Interval between pushes to InfluxDB, in seconds
+    #[arg(long, env, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    pub influx_interval_sec: u64,
+}
+
+#[derive(Args, Clone)]
+pub struct SimulationArgs {
+    /// Generate traffic from this many fake workers instead of connecting to the network
+    #[arg(long, env)]
+    pub simulate: Option<usize>,
+
+    /// Interval between simulated pings of each worker, in seconds
+    #[arg(long, env, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub simulate_ping_interval_sec: u64,
+
+    /// Interval between simulated heartbeats of each worker, in seconds
+    #[arg(long, env, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    pub simulate_heartbeat_interval_sec: u64,
+
+    /// Average share of failed pings. Each worker gets a rate between 0 and twice this value
+    #[arg(long, env, default_value_t = 0.05)]
+    pub simulate_ping_failure_rate: f64,
+
+    /// Average ping round-trip time, in milliseconds
+    #[arg(long, env, default_value_t = 50, value_parser = clap::value_parser!(u64).range(1..))]
+    pub simulate_rtt_ms: u64,
+}
+
+#[derive(Args, Clone)]
+pub struct NatsArgs {
+    /// NATS server URL to publish observed events to
+    #[arg(long, env)]
+    pub nats_url: Option<String>,
+
+    /// Events are published to `<prefix>.<event type>` subjects
+    #[arg(long, env, default_value = "observer")]
+    pub nats_subject_prefix: String,
+
+    /// JetStream stream persisting the published events, created if it doesn't exist
+    #[arg(long, env, default_value = "OBSERVER")]
+    pub nats_stream: String,
+
+    /// Maximum age of events kept in the stream, in seconds
+    #[arg(long, env, default_value_t = 7 * 24 * 3600)]
+    pub nats_max_age_sec: u64,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Args, Clone)]
+pub struct KafkaArgs {
+    /// Comma-separated Kafka bootstrap servers to publish observed events to
+    #[arg(long, env)]
+    pub kafka_brokers: Option<String>,
+
+    /// Events are published to `<prefix><event type>` topics, e.g. `observer.ping`
+    #[arg(long, env, default_value = "observer.")]
+    pub kafka_topic_prefix: String,
+
+    /// Event types to publish (all if not set)
+    #[arg(long, env, value_delimiter = ',')]
+    pub kafka_event_types: Vec<String>,
+
+    /// Time after which an undelivered message is dropped, in seconds
+    #[arg(long, env, default_value_t = 30)]
+    pub kafka_message_timeout_sec: u64,
+}
+
+#[derive(Args, Clone)]
+pub struct AlertArgs {
+    /// Path to a TOML file with alert rules (alerting is disabled if not set)
+    #[arg(long, env)]
+    pub alert_rules: Option<PathBuf>,
+
+    /// Webhook URLs to POST alert notifications to
+    #[arg(long, env, value_delimiter = ',')]
+    pub alert_webhook_urls: Vec<String>,
+
+    /// Slack bot token used to post alerts
+    #[arg(long, env, hide_env_values = true, requires = "alert_slack_channel")]
+    pub alert_slack_token: Option<String>,
+
+    /// Slack channel ID to post alerts to
+    #[arg(long, env, requires = "alert_slack_token")]
+    pub alert_slack_channel: Option<String>,
+
+    /// Telegram bot token used to send alerts
+    #[arg(long, env, hide_env_values = true, requires = "alert_telegram_chat_id")]
+    pub alert_telegram_bot_token: Option<String>,
+
+    /// Telegram chat ID to send alerts to
+    #[arg(long, env, requires = "alert_telegram_bot_token")]
+    pub alert_telegram_chat_id: Option<String>,
+
+    /// Template of Slack and Telegram messages. Supports {rule}, {peer_id}, {status}, {summary},
+    /// {value}, {started_at} and {worker_url} placeholders
+    #[arg(
+        long,
+        env,
+        default_value = "[{status}] {rule}: {summary}\nPeer: {peer_id}\nValue: {value}\n{worker_url}"
+    )]
+    pub alert_message_template: String,
+
+    /// Public URL of this observer, used to link to its API from alert messages
+    #[arg(long, env)]
+    pub observer_url: Option<String>,
+
+    /// Interval between alert rule evaluations, in seconds
+    #[arg(long, env, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    pub alert_evaluation_interval_sec: u64,
+}
+
+#[derive(Args, Clone)]
+pub struct ClickhouseArgs {
+    /// ClickHouse HTTP endpoint to export observed events to
+    #[arg(long, env)]
+    pub clickhouse_url: Option<String>,
+
+    /// ClickHouse database in which the event tables are created
+    #[arg(long, env, default_value = "default")]
+    pub clickhouse_database: String,
+
+    /// ClickHouse user name
+    #[arg(long, env, default_value = "default")]
+    pub clickhouse_user: String,
+
+    /// ClickHouse password
+    #[arg(long, env, hide_env_values = true)]
+    pub clickhouse_password: Option<String>,
+
+    /// Maximum number of rows buffered per table before flushing
+    #[arg(long, env, default_value_t = 10000)]
+    pub clickhouse_batch_size: usize,
+
+    /// Interval between forced flushes, in seconds
+    #[arg(long, env, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub clickhouse_flush_interval_sec: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Copy)]
+pub struct HealthThresholds {
+    pub max_event_age: Duration,
+    pub max_loop_stall: Duration,
+}
+
+/// Settings that can be changed without restarting the observer
+#[derive(Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub log_filter: String,
+    pub probe_interval: Option<Duration>,
+    pub worker_ttl: Duration,
+    pub worker_silence_threshold: Duration,
+    pub registered_silence_window: Duration,
+    pub alert_rules: Arc<Vec<Rule>>,
+}
+
+impl Cli {
+    /// Parses the command line, filling in values missing from the command line
+    /// and the environment from the config file, if one is given
+    pub fn load() -> anyhow::Result<Self> {
+        let cli = Self::parse_from(command_line()?);
+        // Required arguments aren't enforced once any subcommand is given, `generate-key` needs none of them
+        if matches!(cli.command, Some(Command::Run | Command::Check))
+            && cli.key.is_none()
+            && !cli.ephemeral_identity
+            && cli.simulation.simulate.is_none()
+        {
+            Self::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "--key is required unless --ephemeral-identity or --simulate is set",
+                )
+                .exit();
+        }
+        if cli.history_retention_sec < cli.history_resolution_sec {
+            Self::command()
+                .error(
+                    clap::error::ErrorKind::ValueValidation,
+                    "--history-retention-sec must be at least --history-resolution-sec",
+                )
+                .exit();
+        }
+        Ok(cli)
+    }
+
+    /// Returns the value of every argument after applying the config file and environment,
+    /// keyed like the config file, with secrets redacted
+    pub fn effective_config() -> anyhow::Result<serde_json::Value> {
+        let command = Self::command();
+        let matches = command.clone().try_get_matches_from(command_line()?)?;
+        let mut config = serde_json::Map::new();
+        for arg in command.get_arguments() {
+            let id = arg.get_id().as_str();
+            let value = match matches.get_raw(id) {
+                None => serde_json::Value::Null,
+                Some(_) if arg.is_hide_env_values_set() => "<redacted>".into(),
+                Some(raw) => {
+                    let mut values = raw.map(|v| v.to_string_lossy().into_owned());
+                    match arg.get_action() {
+                        ArgAction::SetTrue | ArgAction::SetFalse => {
+                            values.next().is_some_and(|v| v == "true").into()
+                        }
+                        ArgAction::Append => values.collect::<Vec<_>>().into(),
+                        _ => values.next().into(),
+                    }
+                }
+            };
+            config.insert(id.to_owned(), value);
+        }
+        Ok(config.into())
+    }
+
+    /// Re-reads the config file and parses the arguments again without exiting on errors
+    pub fn reload() -> anyhow::Result<Self> {
+        Ok(Self::try_parse_from(command_line()?)?)
+    }
+
+    pub fn tls(&self) -> Option<TlsPaths> {
+        Some(TlsPaths {
+            cert: self.tls_cert.clone()?,
+            key: self.tls_key.clone()?,
+        })
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_addr
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], self.port)))
+    }
+
+    /// Builds the HTTP client shared by all subsystems that talk to external endpoints
+    pub fn http_client(&self) -> anyhow::Result<reqwest::Client> {
+        Ok(reqwest::Client::builder()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .connect_timeout(Duration::from_secs(self.http_connect_timeout_sec))
+            .timeout(Duration::from_secs(self.http_timeout_sec))
+            .pool_max_idle_per_host(self.http_pool_max_idle_per_host)
+            .build()?)
+    }
+
+    pub fn score_weights(&self) -> ScoreWeights {
+        ScoreWeights {
+            uptime: self.score_weight_uptime,
+            ping_success: self.score_weight_ping_success,
+            missing_chunks: self.score_weight_missing_chunks,
+            reachability: self.score_weight_reachability,
+            missing_chunks_threshold: self.score_missing_chunks_threshold,
+        }
+    }
+
+    pub fn health_thresholds(&self) -> HealthThresholds {
+        HealthThresholds {
+            max_event_age: Duration::from_secs(self.readiness_max_event_age_sec),
+            max_loop_stall: Duration::from_secs(self.liveness_max_stall_sec),
+        }
+    }
+
+    /// Also reads the alert rules file, so that its changes are picked up on reload
+    pub fn runtime_config(&self) -> anyhow::Result<RuntimeConfig> {
+        let alert_rules = match &self.alerts.alert_rules {
+            Some(path) => load_rules(path)?,
+            None => Vec::new(),
+        };
+        Ok(RuntimeConfig {
+            log_filter: self.log_filter.clone(),
+            probe_interval: self.probe_interval_sec.map(Duration::from_secs),
+            worker_ttl: Duration::from_secs(self.worker_ttl_sec),
+            worker_silence_threshold: Duration::from_secs(self.worker_silence_threshold_sec),
+            registered_silence_window: Duration::from_secs(self.registered_silence_window_sec),
+            alert_rules: Arc::new(alert_rules),
+        })
+    }
+}
+
+/// Commands run instead of the observer
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the observer, which is also the default without a subcommand
+    Run,
+    /// Validate the configuration and check the key, listen address and RPC endpoints, then exit
+    Check,
+    /// Print the effective configuration, after applying the config file and environment, as JSON
+    PrintConfig,
+    /// Generate a new libp2p key and write it to the given file
+    GenerateKey { path: PathBuf },
+    /// Print the peer ID of the key stored in the given file
+    PeerId { path: PathBuf },
+    /// Feed events recorded with --record-events through the event handling and print the resulting metrics
+    Replay { path: PathBuf },
+}
+
+/// Returns the process arguments with the entries of the config file, if one is given,
+/// inserted as options in front of them
+fn command_line() -> anyhow::Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if let Some(path) = config_path() {
+        let options = read_config_file(&path)
+            .with_context(|| format!("Failed to load config file {}", path.display()))?;
+        args.splice(1..1, options);
+    }
+    Ok(args)
+}
+
+fn config_path() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(path.into());
+        }
+    }
+    std::env::var_os("CONFIG").map(PathBuf::from)
+}
+
+fn read_config_file(path: &Path) -> anyhow::Result<Vec<OsString>> {
+    let table: toml::Table = std::fs::read_to_string(path)?.parse()?;
+    let command = Cli::command();
+    // Only tells where values come from, errors are reported by the final parse
+    let given = command
+        .clone()
+        .ignore_errors(true)
+        .get_matches_from(std::env::args_os());
+    config_options(&command, table, &given)
+}
+
+/// Turns config file entries into command line options for the matching arguments,
+/// skipping those already given on the command line or in the environment.
+/// Nested tables are flattened, so `[clickhouse] url = ...` sets `clickhouse_url`.
+fn config_options(
+    command: &clap::Command,
+    table: toml::Table,
+    given: &clap::ArgMatches,
+) -> anyhow::Result<Vec<OsString>> {
+    let mut entries = Vec::new();
+    flatten_table("", table, &mut entries)?;
+
+    let mut options = Vec::new();
+    for (key, values) in entries {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str())
+            .ok_or_else(|| anyhow!("Unknown config option '{key}'"))?;
+        let long = arg
+            .get_long()
+            .ok_or_else(|| anyhow!("Option '{key}' can't be set from the config file"))?;
+        if matches!(
+            given.value_source(&key),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        match arg.get_action() {
+            ArgAction::SetTrue => match values.as_slice() {
+                [value] if value == "true" => options.push(OsString::from(format!("--{long}"))),
+                [value] if value == "false" => {}
+                _ => return Err(anyhow!("Option '{key}' must be a boolean")),
+            },
+            ArgAction::Append => options.extend(
+                values
+                    .iter()
+                    .map(|value| OsString::from(format!("--{long}={value}"))),
+            ),
+            _ => options.push(OsString::from(format!("--{long}={}", values.join(",")))),
+        }
+    }
+    Ok(options)
+}
+
+fn flatten_table(
+    prefix: &str,
+    table: toml::Table,
+    entries: &mut Vec<(String, Vec<String>)>,
+) -> anyhow::Result<()> {
+    for (key, value) in table {
+        let key = format!("{prefix}{}", key.replace('-', "_"));
+        match value {
+            toml::Value::Table(table) => flatten_table(&format!("{key}_"), table, entries)?,
+            toml::Value::Array(values) => {
+                let values = values
+                    .into_iter()
+                    .map(config_value_to_string)
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                entries.push((key, values));
+            }
+            value => entries.push((key, vec![config_value_to_string(value)?])),
+        }
+    }
+    Ok(())
+}
+
+fn config_value_to_string(value: toml::Value) -> anyhow::Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(d) => Ok(d.to_string()),
+        value => Err(anyhow!("Unsupported config value: {value}")),
+    }
+}
+
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got '{s}'"))?;
+    if key.is_empty() {
+        return Err(format!("empty label name in '{s}'"));
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(config: &str, args: &[&str]) -> anyhow::Result<Vec<String>> {
+        let command = Cli::command().mut_args(|arg| arg.env(None));
+        let given = command
+            .clone()
+            .ignore_errors(true)
+            .get_matches_from(std::iter::once("observer").chain(args.iter().copied()));
+        let options = config_options(&command, config.parse()?, &given)?;
+        let mut options: Vec<_> = options
+            .into_iter()
+            .map(|option| option.into_string().unwrap())
+            .collect();
+        // The order of config file entries isn't preserved
+        options.sort();
+        Ok(options)
+    }
+
+    #[test]
+    fn config_entries_become_options() {
+        let options = options(
+            r#"
+            log-filter = "debug"
+            ephemeral_identity = true
+            block_peers = ["a", "b"]
+            p2p_listen_addrs = ["/ip4/0.0.0.0/udp/1/quic-v1", "/ip4/0.0.0.0/udp/2/quic-v1"]
+            history_resolution_sec = 30
+
+            [clickhouse]
+            url = "http://localhost:8123"
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            [
+                "--block-peer=a",
+                "--block-peer=b",
+                "--clickhouse-url=http://localhost:8123",
+                "--ephemeral-identity",
+                "--history-resolution-sec=30",
+                "--log-filter=debug",
+                "--p2p-listen-addrs=/ip4/0.0.0.0/udp/1/quic-v1",
+                "--p2p-listen-addrs=/ip4/0.0.0.0/udp/2/quic-v1",
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let options = options(
+            r#"
+            log_filter = "debug"
+            history_resolution_sec = 30
+            "#,
+            &["--log-filter", "warn"],
+        )
+        .unwrap();
+        assert_eq!(options, ["--history-resolution-sec=30"]);
+    }
+
+    #[test]
+    fn false_flags_are_omitted() {
+        assert!(options("ephemeral_identity = false", &[])
+            .unwrap()
+            .is_empty());
+        assert!(options("ephemeral_identity = 1", &[]).is_err());
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let error = options("no_such_option = 1", &[]).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown config option 'no_such_option'");
+    }
+
+    #[test]
+    fn labels_are_parsed() {
+        assert_eq!(
+            parse_label("region=eu=west"),
+            Ok(("region".to_owned(), "eu=west".to_owned()))
+        );
+        assert_eq!(
+            parse_label("empty="),
+            Ok(("empty".to_owned(), String::new()))
+        );
+        assert!(parse_label("region").is_err());
+        assert!(parse_label("=eu").is_err());
+    }
 }
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    cli::ClickhouseArgs,
+    events::{EventKind, ObservedEvent},
+};
+
+struct Table {
+    name: &'static str,
+    columns: &'static str,
+    rows: Vec<String>,
+}
+
+impl Table {
+    fn new(name: &'static str, columns: &'static str) -> Self {
+        Self {
+            name,
+            columns,
+            rows: Vec::new(),
+        }
+    }
+}
+
+/// Batches observed events and inserts them into ClickHouse over its HTTP interface
+pub struct ClickhouseSink {
+    client: reqwest::Client,
+    url: String,
+    args: ClickhouseArgs,
+    pings: Table,
+    heartbeats: Table,
+    peers_seen: Table,
+}
+
+impl ClickhouseSink {
+    pub fn new(client: reqwest::Client, url: String, args: ClickhouseArgs) -> Self {
+        Self {
+            client,
+            url,
+            args,
+            pings: Table::new(
+                "observer_pings",
+                "timestamp DateTime, peer_id String, rtt_secs Nullable(Float64), error Nullable(String)",
+            ),
+            heartbeats: Table::new(
+                "observer_heartbeats",
+                "timestamp DateTime, peer_id String, missing_chunks UInt64, stored_bytes UInt64, assignment_timestamp DateTime",
+            ),
+            peers_seen: Table::new(
+                "observer_peers_seen",
+                "timestamp DateTime, peer_id String, address String",
+            ),
+        }
+    }
+
+    pub async fn run(mut self, mut events: broadcast::Receiver<ObservedEvent>) {
+        if let Err(e) = self.create_tables().await {
+            log::error!("Failed to create ClickHouse tables: {e:?}");
+        }
+
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.args.clickhouse_flush_interval_sec));
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => self.push(event).await,
+                    Err(RecvError::Lagged(n)) => {
+                        log::warn!("ClickHouse sink is lagging behind, {n} events dropped");
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = interval.tick() => self.flush_all().await,
+            }
+        }
+        self.flush_all().await;
+    }
+
+    async fn push(&mut self, event: ObservedEvent) {
+        let timestamp = event.timestamp;
+        let (table, row) = match event.kind {
+            EventKind::Ping {
+                peer_id,
+                rtt_secs,
+                error,
+            } => (
+                &mut self.pings,
+                json!({
+                    "timestamp": timestamp,
+                    "peer_id": peer_id,
+                    "rtt_secs": rtt_secs,
+                    "error": error,
+                }),
+            ),
+            EventKind::WorkerHeartbeat {
+                peer_id,
+                missing_chunks,
+                stored_bytes,
+                assignment_timestamp,
+            } => (
+                &mut self.heartbeats,
+                json!({
+                    "timestamp": timestamp,
+                    "peer_id": peer_id,
+                    "missing_chunks": missing_chunks,
+                    "stored_bytes": stored_bytes,
+                    "assignment_timestamp": assignment_timestamp,
+                }),
+            ),
+            EventKind::PeerSeen { peer_id, address } => (
+                &mut self.peers_seen,
+                json!({
+                    "timestamp": timestamp,
+                    "peer_id": peer_id,
+                    "address": address,
+                }),
+            ),
+            EventKind::PeerIdentified { .. } | EventKind::DialProbe { .. } => return,
+        };
+        table.rows.push(row.to_string());
+
+        if table.rows.len() >= self.args.clickhouse_batch_size {
+            let rows = std::mem::take(&mut table.rows);
+            let name = table.name;
+            Self::insert_rows(&self.client, &self.url, &self.args, name, rows).await;
+        }
+    }
+
+    async fn flush_all(&mut self) {
+        for table in [&mut self.pings, &mut self.heartbeats, &mut self.peers_seen] {
+            if table.rows.is_empty() {
+                continue;
+            }
+            let rows = std::mem::take(&mut table.rows);
+            let name = table.name;
+            Self::insert_rows(&self.client, &self.url, &self.args, name, rows).await;
+        }
+    }
+
+    async fn insert_rows(
+        client: &reqwest::Client,
+        url: &str,
+        args: &ClickhouseArgs,
+        table: &str,
+        rows: Vec<String>,
+    ) {
+        let count = rows.len();
+        let query = format!(
+            "INSERT INTO {}.{table} FORMAT JSONEachRow",
+            args.clickhouse_database
+        );
+        match Self::execute(client, url, args, query, rows.join("\n")).await {
+            Ok(()) => log::debug!("Inserted {count} rows into {table}"),
+            Err(e) => log::warn!("Failed to insert {count} rows into {table}: {e:?}"),
+        }
+    }
+
+    async fn create_tables(&self) -> Result<()> {
+        for table in [&self.pings, &self.heartbeats, &self.peers_seen] {
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {}.{} ({}) ENGINE = MergeTree ORDER BY (peer_id, timestamp)",
+                self.args.clickhouse_database, table.name, table.columns
+            );
+            Self::execute(&self.client, &self.url, &self.args, query, String::new()).await?;
+        }
+        Ok(())
+    }
+
+    async fn execute(
+        client: &reqwest::Client,
+        url: &str,
+        args: &ClickhouseArgs,
+        query: String,
+        body: String,
+    ) -> Result<()> {
+        let mut request = client
+            .post(url)
+            .query(&[("query", query)])
+            .header("X-ClickHouse-User", &args.clickhouse_user)
+            .body(body);
+        if let Some(password) = &args.clickhouse_password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            anyhow::bail!("ClickHouse returned {status}: {text}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! Polls the on-chain worker registry so that observed peers can be matched
+//! against registered workers.
+
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use sqd_contract_client::{Client, Network, RpcArgs};
+
+use crate::{metrics::Metrics, state::State};
+
+/// A worker registered in the registry contract
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct RegisteredWorker {
+    pub peer_id: String,
+    pub onchain_id: String,
+    pub bond_sqd: f64,
+    pub registered_at: u64,
+    pub deregistered_at: Option<u64>,
+}
+
+pub async fn connect(
+    rpc_url: String,
+    l1_rpc_url: String,
+    network: Network,
+) -> Result<Box<dyn Client>> {
+    let rpc_args = RpcArgs {
+        rpc_url,
+        l1_rpc_url,
+        network,
+    };
+    Ok(sqd_contract_client::get_client(&rpc_args).await?)
+}
+
+/// Reads the current epoch number, which is enough to verify that the RPC endpoints work
+pub async fn current_epoch(client: &dyn Client) -> Result<u32> {
+    Ok(client.current_epoch().await?)
+}
+
+/// Epoch-relative gauges need to stay fresh, so the epoch is read more often than the registry
+const EPOCH_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Periodically reads the registered workers and the current epoch and updates the state and metrics
+pub async fn run(
+    client: Box<dyn Client>,
+    state: Arc<State>,
+    metrics: Arc<Metrics>,
+    period: Duration,
+) {
+    let mut registry_interval = tokio::time::interval(period);
+    let mut epoch_interval = tokio::time::interval(EPOCH_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = registry_interval.tick() => match registered_workers(client.as_ref()).await {
+                Ok(workers) => {
+                    log::debug!("Registry contract lists {} workers", workers.len());
+                    metrics.registered_workers(&workers);
+                    state.set_registered_workers(workers);
+                    metrics.unregistered_peers(state.unregistered_pinging_peers());
+                }
+                Err(e) => log::warn!("Failed to read registered workers: {e:?}"),
+            },
+            _ = epoch_interval.tick() => match epoch(client.as_ref()).await {
+                Ok(epoch) => metrics.epoch(epoch.number, epoch.started_at, epoch.length),
+                Err(e) => log::warn!("Failed to read the current epoch: {e:?}"),
+            },
+        }
+    }
+}
+
+struct Epoch {
+    number: u32,
+    started_at: i64,
+    length: Duration,
+}
+
+async fn epoch(client: &dyn Client) -> Result<Epoch> {
+    let number = client.current_epoch().await?;
+    let started_at = client
+        .current_epoch_start()
+        .await?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs() as i64;
+    let length = client.epoch_length().await?;
+    Ok(Epoch {
+        number,
+        started_at,
+        length,
+    })
+}
+
+async fn registered_workers(client: &dyn Client) -> Result<Vec<RegisteredWorker>> {
+    let workers = client.active_workers().await?;
+    Ok(workers
+        .into_iter()
+        .map(|worker| RegisteredWorker {
+            peer_id: worker.peer_id.to_string(),
+            onchain_id: worker.onchain_id.to_string(),
+            // The total supply is far below u128::MAX, so saturating never loses real bonds
+            bond_sqd: wei_to_sqd(u128::try_from(worker.bond).unwrap_or(u128::MAX)),
+            registered_at: worker.registered_at as u64,
+            deregistered_at: worker.deregistered_at.map(|block| block as u64),
+        })
+        .collect())
+}
+
+/// Converts an amount with 18 decimals to SQD, keeping the precision of the fractional part
+fn wei_to_sqd(wei: u128) -> f64 {
+    const WEI_PER_SQD: u128 = 10u128.pow(18);
+    (wei / WEI_PER_SQD) as f64 + (wei % WEI_PER_SQD) as f64 / WEI_PER_SQD as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bond_is_converted_to_sqd() {
+        assert_eq!(wei_to_sqd(0), 0.0);
+        assert_eq!(wei_to_sqd(100_000 * 10u128.pow(18)), 100_000.0);
+        assert_eq!(wei_to_sqd(15 * 10u128.pow(17)), 1.5);
+        assert_eq!(wei_to_sqd(1), 1e-18);
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{metrics::now, transport};
+
+/// A normalized event as handled by the observer and published to the sinks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservedEvent {
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    PeerSeen {
+        peer_id: String,
+        address: String,
+    },
+    PeerIdentified {
+        peer_id: String,
+        agent_version: String,
+        protocol_version: String,
+        protocols: Vec<String>,
+        listen_addrs: Vec<String>,
+    },
+    WorkerHeartbeat {
+        peer_id: String,
+        missing_chunks: u64,
+        stored_bytes: u64,
+        assignment_timestamp: i64,
+    },
+    Ping {
+        peer_id: String,
+        rtt_secs: Option<f64>,
+        error: Option<String>,
+    },
+    DialProbe {
+        peer_id: String,
+        duration_secs: Option<f64>,
+        error: Option<String>,
+    },
+}
+
+impl EventKind {
+    /// The name under which the event type is serialized
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            EventKind::PeerSeen { .. } => "peer_seen",
+            EventKind::PeerIdentified { .. } => "peer_identified",
+            EventKind::WorkerHeartbeat { .. } => "worker_heartbeat",
+            EventKind::Ping { .. } => "ping",
+            EventKind::DialProbe { .. } => "dial_probe",
+        }
+    }
+
+    pub fn peer_id(&self) -> &str {
+        match self {
+            EventKind::PeerSeen { peer_id, .. }
+            | EventKind::PeerIdentified { peer_id, .. }
+            | EventKind::WorkerHeartbeat { peer_id, .. }
+            | EventKind::Ping { peer_id, .. }
+            | EventKind::DialProbe { peer_id, .. } => peer_id,
+        }
+    }
+}
+
+impl From<transport::Event> for ObservedEvent {
+    fn from(event: transport::Event) -> Self {
+        let kind = match event {
+            transport::Event::PeerSeen(event) => {
+                let mut address = event.address;
+                while let Some(libp2p::multiaddr::Protocol::P2p(_)) = address.iter().last() {
+                    address.pop();
+                }
+                EventKind::PeerSeen {
+                    peer_id: event.peer_id.to_string(),
+                    address: address.to_string(),
+                }
+            }
+            transport::Event::PeerIdentified(event) => EventKind::PeerIdentified {
+                peer_id: event.peer_id.to_string(),
+                agent_version: event.agent_version,
+                protocol_version: event.protocol_version,
+                protocols: event.protocols.iter().map(|p| p.to_string()).collect(),
+                listen_addrs: event.listen_addrs.iter().map(|a| a.to_string()).collect(),
+            },
+            transport::Event::WorkerHeartbeat(event) => {
+                let peer_id = event
+                    .peer_id
+                    .map(|peer_id| peer_id.to_string())
+                    .unwrap_or_else(|| {
+                        log::warn!("Received heartbeat from unknown peer");
+                        "unknown".to_string()
+                    });
+
+                let missing_chunks = event
+                    .heartbeat
+                    .missing_chunks
+                    .map(|bitstring| bitstring.ones())
+                    .unwrap_or_else(|| {
+                        log::warn!("Received heartbeat without missing_chunks from {peer_id}");
+                        0
+                    });
+
+                let assignment_timestamp = chrono::NaiveDateTime::parse_and_remainder(
+                    &event.heartbeat.assignment_id,
+                    "%Y-%m-%dT%H:%M:%S",
+                )
+                .map(|(time, _)| time.and_utc().timestamp())
+                .unwrap_or_else(|e| {
+                    log::warn!(
+                        "Failed to parse assignment_id '{}': {e}",
+                        &event.heartbeat.assignment_id
+                    );
+                    0
+                });
+
+                EventKind::WorkerHeartbeat {
+                    peer_id,
+                    missing_chunks,
+                    stored_bytes: event.heartbeat.stored_bytes.unwrap_or_default(),
+                    assignment_timestamp,
+                }
+            }
+            transport::Event::Ping(event) => EventKind::Ping {
+                peer_id: event.peer.to_string(),
+                rtt_secs: event.result.as_ref().ok().map(|d| d.as_secs_f64()),
+                error: event.result.err().map(|e| e.to_string()),
+            },
+            transport::Event::DialProbe(event) => EventKind::DialProbe {
+                peer_id: event.peer_id.to_string(),
+                duration_secs: event.result.ok().map(|d| d.as_secs_f64()),
+                error: event.result.err().map(ToOwned::to_owned),
+            },
+        };
+        Self {
+            timestamp: now(),
+            kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_serialized_flat() {
+        let event = ObservedEvent {
+            timestamp: 1_700_000_000,
+            kind: EventKind::Ping {
+                peer_id: "a".to_owned(),
+                rtt_secs: Some(0.05),
+                error: None,
+            },
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": 1_700_000_000,
+                "type": "ping",
+                "peer_id": "a",
+                "rtt_secs": 0.05,
+                "error": null,
+            })
+        );
+        let parsed: ObservedEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.timestamp, event.timestamp);
+        assert_eq!(parsed.kind.type_name(), "ping");
+        assert_eq!(parsed.kind.peer_id(), "a");
+    }
+
+    #[test]
+    fn type_names_match_serialization() {
+        let kinds = [
+            EventKind::PeerSeen {
+                peer_id: "a".to_owned(),
+                address: "/ip4/10.0.0.1/udp/1/quic-v1".to_owned(),
+            },
+            EventKind::WorkerHeartbeat {
+                peer_id: "a".to_owned(),
+                missing_chunks: 0,
+                stored_bytes: 0,
+                assignment_timestamp: 0,
+            },
+            EventKind::DialProbe {
+                peer_id: "a".to_owned(),
+                duration_secs: None,
+                error: Some("Timeout".to_owned()),
+            },
+        ];
+        for kind in kinds {
+            let json = serde_json::to_value(&kind).unwrap();
+            assert_eq!(json["type"], kind.type_name());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Resolves worker addresses against MaxMind databases to report how workers
+//! are distributed across countries and autonomous systems.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use crate::{
+    metrics::Metrics,
+    state::{State, WorkerInfo},
+};
+
+const UNKNOWN: &str = "unknown";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GeoInfo {
+    pub country: String,
+    pub asn: String,
+    pub as_org: String,
+}
+
+impl Default for GeoInfo {
+    fn default() -> Self {
+        Self {
+            country: UNKNOWN.to_owned(),
+            asn: UNKNOWN.to_owned(),
+            as_org: UNKNOWN.to_owned(),
+        }
+    }
+}
+
+pub struct GeoIp {
+    country_db: Option<Reader<Vec<u8>>>,
+    asn_db: Option<Reader<Vec<u8>>>,
+    /// Lookups are cached because worker addresses rarely change
+    cache: Mutex<HashMap<IpAddr, GeoInfo>>,
+}
+
+impl GeoIp {
+    pub fn open(country_db: Option<&Path>, asn_db: Option<&Path>) -> Result<Self> {
+        let open = |path: &Path| {
+            Reader::open_readfile(path)
+                .with_context(|| format!("Couldn't open GeoIP database {}", path.display()))
+        };
+        Ok(Self {
+            country_db: country_db.map(open).transpose()?,
+            asn_db: asn_db.map(open).transpose()?,
+            cache: Default::default(),
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        if let Some(info) = self.cache.lock().unwrap().get(&ip) {
+            return info.clone();
+        }
+        let mut info = GeoInfo::default();
+        if let Some(db) = &self.country_db {
+            match db.lookup::<geoip2::Country>(ip) {
+                Ok(country) => {
+                    if let Some(code) = country.country.and_then(|c| c.iso_code) {
+                        info.country = code.to_owned();
+                    }
+                }
+                Err(MaxMindDBError::AddressNotFoundError(_)) => {}
+                Err(e) => log::warn!("GeoIP country lookup of {ip} failed: {e}"),
+            }
+        }
+        if let Some(db) = &self.asn_db {
+            match db.lookup::<geoip2::Asn>(ip) {
+                Ok(asn) => {
+                    if let Some(number) = asn.autonomous_system_number {
+                        info.asn = number.to_string();
+                    }
+                    if let Some(org) = asn.autonomous_system_organization {
+                        info.as_org = org.to_owned();
+                    }
+                }
+                Err(MaxMindDBError::AddressNotFoundError(_)) => {}
+                Err(e) => log::warn!("GeoIP ASN lookup of {ip} failed: {e}"),
+            }
+        }
+        self.cache.lock().unwrap().insert(ip, info.clone());
+        info
+    }
+
+    /// Periodically recomputes the per-country and per-ASN worker distribution
+    pub async fn run(self, state: Arc<State>, metrics: Arc<Metrics>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let mut distribution: HashMap<GeoInfo, (i64, u64)> = HashMap::new();
+            for worker in state.workers().into_iter().filter(WorkerInfo::is_worker) {
+                let info = worker
+                    .addresses
+                    .iter()
+                    .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+                    .find_map(|addr| public_ip(&addr))
+                    .map(|ip| self.lookup(ip))
+                    .unwrap_or_default();
+                let entry = distribution.entry(info).or_default();
+                entry.0 += 1;
+                entry.1 += worker.stored_bytes.unwrap_or_default();
+            }
+            metrics.geo_distribution(distribution);
+        }
+    }
+}
+
+fn public_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    let ip = addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })?;
+    let is_public = match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified())
+        }
+        IpAddr::V6(ip) => {
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local())
+        }
+    };
+    is_public.then_some(ip)
+}
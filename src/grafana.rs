@@ -0,0 +1,142 @@
+//! Generates a Grafana dashboard from the registered metric families, so that
+//! it never falls out of sync with the metrics the observer actually exports.
+
+use std::collections::BTreeMap;
+
+use prometheus_client::registry::Registry;
+use serde_json::{json, Value};
+
+use crate::openmetrics::{self, MetricFamily, MetricType};
+
+const PANEL_WIDTH: u64 = 12;
+const PANEL_HEIGHT: u64 = 8;
+
+/// Builds the dashboard with one row per subsystem, the first word of the metric name
+pub fn dashboard(registry: &Registry, prefix: Option<&str>, network: &str) -> Value {
+    let prefix = prefix
+        .map(|p| format!("{}_", p.trim_end_matches('_')))
+        .filter(|p| p != "_")
+        .unwrap_or_default();
+    let families = openmetrics::collect(registry);
+
+    let mut subsystems: BTreeMap<String, Vec<&MetricFamily>> = BTreeMap::new();
+    for family in &families {
+        if matches!(family.metric_type, MetricType::Info | MetricType::Unknown) {
+            continue;
+        }
+        let name = family.name.strip_prefix(&prefix).unwrap_or(&family.name);
+        let subsystem = name.split('_').next().unwrap_or(name);
+        subsystems
+            .entry(subsystem.to_owned())
+            .or_default()
+            .push(family);
+    }
+
+    let mut panels = Vec::new();
+    let mut y = 0;
+    for (id, (subsystem, families)) in subsystems.iter().enumerate() {
+        panels.push(json!({
+            "type": "row",
+            "title": subsystem,
+            "collapsed": false,
+            "gridPos": { "x": 0, "y": y, "w": 2 * PANEL_WIDTH, "h": 1 },
+            "id": 1000 + id,
+        }));
+        y += 1;
+        for (i, family) in families.iter().enumerate() {
+            panels.push(json!({
+                "type": "timeseries",
+                "title": family.name,
+                "description": family.help,
+                "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                "gridPos": {
+                    "x": (i as u64 % 2) * PANEL_WIDTH,
+                    "y": y + (i as u64 / 2) * PANEL_HEIGHT,
+                    "w": PANEL_WIDTH,
+                    "h": PANEL_HEIGHT,
+                },
+                "fieldConfig": { "defaults": { "unit": grafana_unit(&family.unit) }, "overrides": [] },
+                "targets": [{ "refId": "A", "expr": query(family), "legendFormat": "__auto" }],
+                "id": panels.len(),
+            }));
+        }
+        y += families.len().div_ceil(2) as u64 * PANEL_HEIGHT;
+    }
+
+    // Any per-worker family works as the source of the peer ID values
+    let peer_id_source = families
+        .iter()
+        .find(|family| is_per_worker(family))
+        .map(|family| family.samples[0].name.clone());
+
+    json!({
+        "title": format!("Network observer ({network})"),
+        "uid": format!("sqd-observer-{network}"),
+        "tags": ["sqd", "network-observer"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "refresh": "30s",
+        "time": { "from": "now-6h", "to": "now" },
+        "templating": {
+            "list": [
+                {
+                    "name": "datasource",
+                    "type": "datasource",
+                    "query": "prometheus",
+                },
+                {
+                    "name": "peer_id",
+                    "label": "Worker",
+                    "type": "query",
+                    "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                    "query": peer_id_source
+                        .map(|name| format!("label_values({name}, peer_id)"))
+                        .unwrap_or_default(),
+                    "refresh": 2,
+                    "multi": true,
+                    "includeAll": true,
+                    "allValue": ".*",
+                    "current": { "text": "All", "value": "$__all" },
+                },
+            ],
+        },
+        "panels": panels,
+    })
+}
+
+fn is_per_worker(family: &MetricFamily) -> bool {
+    family
+        .samples
+        .first()
+        .is_some_and(|sample| sample.labels.iter().any(|(k, _)| k == "peer_id"))
+}
+
+/// Returns the PromQL expression plotting the family, filtered by the selected workers
+fn query(family: &MetricFamily) -> String {
+    let selector = if is_per_worker(family) {
+        r#"{peer_id=~"$peer_id"}"#
+    } else {
+        ""
+    };
+    match family.metric_type {
+        MetricType::Counter => format!("rate({}_total{selector}[$__rate_interval])", family.name),
+        MetricType::Histogram if is_per_worker(family) => format!(
+            "histogram_quantile(0.95, sum by (peer_id, le) (rate({}_bucket{selector}[$__rate_interval])))",
+            family.name
+        ),
+        MetricType::Histogram => format!(
+            "histogram_quantile(0.95, sum by (le) (rate({}_bucket[$__rate_interval])))",
+            family.name
+        ),
+        _ => format!("{}{selector}", family.name),
+    }
+}
+
+fn grafana_unit(unit: &str) -> &'static str {
+    match unit {
+        "seconds" => "s",
+        "bytes" => "bytes",
+        "ratio" => "percentunit",
+        _ => "short",
+    }
+}
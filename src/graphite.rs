@@ -0,0 +1,87 @@
+//! Publishes the per-worker series to Graphite using the plaintext protocol.
+
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use prometheus_client::registry::Registry;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+use crate::{
+    metrics::now,
+    openmetrics::{self, MetricType},
+};
+
+/// Periodically sends the series to the Graphite server, connecting anew each time
+pub async fn run(registry: Arc<Registry>, addr: String, prefix: String, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let lines = worker_lines(&registry, &prefix);
+        match send(&addr, lines.as_bytes()).await {
+            Ok(()) => log::debug!("Sent metrics to Graphite at {addr}"),
+            Err(e) => log::warn!("Failed to send metrics to Graphite at {addr}: {e:?}"),
+        }
+    }
+}
+
+/// Renders the counters and gauges labeled with a peer ID as
+/// `<prefix>.<network>.<peer_id>.<series>[.<other label values>] <value> <timestamp>`
+fn worker_lines(registry: &Registry, prefix: &str) -> String {
+    let timestamp = now();
+    let mut lines = String::new();
+    for family in openmetrics::collect(registry) {
+        if !matches!(family.metric_type, MetricType::Counter | MetricType::Gauge) {
+            continue;
+        }
+        for sample in family.samples {
+            let label = |name: &str| {
+                sample
+                    .labels
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.as_str())
+            };
+            // Addresses would make paths unbounded, so per-address series are left out
+            let (Some(peer_id), None) = (label("peer_id"), label("addr")) else {
+                continue;
+            };
+            if !sample.value.is_finite() {
+                continue;
+            }
+            let mut path = format!(
+                "{}.{}.{}.{}",
+                prefix,
+                sanitize(label("network").unwrap_or("unknown")),
+                sanitize(peer_id),
+                sanitize(&sample.name)
+            );
+            for (name, value) in &sample.labels {
+                if name != "peer_id" && name != "network" {
+                    write!(path, ".{}", sanitize(value)).unwrap();
+                }
+            }
+            writeln!(lines, "{path} {} {timestamp}", sample.value).unwrap();
+        }
+    }
+    lines
+}
+
+/// Replaces characters that would split or break a path component
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+async fn send(addr: &str, lines: &[u8]) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(lines).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! GraphQL API over the observer state with nested per-worker data.
+
+use std::sync::Arc;
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject,
+};
+
+use crate::{
+    state::{State, WorkerInfo, WorkerStatus},
+    uptime::{Interval, WindowUptime},
+};
+
+pub type ObserverSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn schema(state: Arc<State>) -> ObserverSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(state)
+        .finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Known workers, optionally filtered. Other peers aren't included.
+    async fn workers(
+        &self,
+        ctx: &Context<'_>,
+        online: Option<bool>,
+        version: Option<String>,
+        registered: Option<bool>,
+    ) -> Vec<Worker> {
+        let state = ctx.data_unchecked::<Arc<State>>();
+        state
+            .workers()
+            .into_iter()
+            .filter(WorkerInfo::is_worker)
+            .filter(|w| online.is_none_or(|online| (w.status == WorkerStatus::Online) == online))
+            .filter(|w| {
+                version
+                    .as_ref()
+                    .is_none_or(|version| w.role_and_version().1 == *version)
+            })
+            .filter(|w| registered.is_none_or(|registered| w.registered == Some(registered)))
+            .map(Worker::from)
+            .collect()
+    }
+
+    async fn worker(&self, ctx: &Context<'_>, peer_id: String) -> Option<Worker> {
+        let state = ctx.data_unchecked::<Arc<State>>();
+        state
+            .workers()
+            .into_iter()
+            .find(|w| w.peer_id == peer_id)
+            .map(Worker::from)
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Worker {
+    peer_id: String,
+    addresses: Vec<String>,
+    agent_version: Option<String>,
+    role: String,
+    version: String,
+    online: bool,
+    last_active: Option<i64>,
+    last_seen: Option<i64>,
+    last_ping: Option<i64>,
+    last_ping_rtt_secs: Option<f64>,
+    last_dial: Option<i64>,
+    last_dial_ok: Option<bool>,
+    stored_bytes: Option<u64>,
+    missing_chunks: Option<u64>,
+    assignment_timestamp: Option<i64>,
+    registered: Option<bool>,
+    onchain_id: Option<String>,
+    score: Option<f64>,
+}
+
+impl From<WorkerInfo> for Worker {
+    fn from(info: WorkerInfo) -> Self {
+        let (role, version) = info.role_and_version();
+        Self {
+            peer_id: info.peer_id,
+            addresses: info.addresses.into_iter().collect(),
+            agent_version: info.version,
+            role,
+            version,
+            online: info.status == WorkerStatus::Online,
+            last_active: info.last_active,
+            last_seen: info.last_seen,
+            last_ping: info.last_ping,
+            last_ping_rtt_secs: info.last_ping_rtt_secs,
+            last_dial: info.last_dial,
+            last_dial_ok: info.last_dial_ok,
+            stored_bytes: info.stored_bytes,
+            missing_chunks: info.missing_chunks,
+            assignment_timestamp: info.assignment_timestamp,
+            registered: info.registered,
+            onchain_id: info.onchain_id,
+            score: info.score,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct Uptime {
+    windows: Vec<UptimeWindow>,
+    /// Periods of continuous ping responses
+    intervals: Vec<OnlineInterval>,
+}
+
+#[derive(SimpleObject)]
+struct UptimeWindow {
+    window: String,
+    window_secs: u64,
+    uptime_ratio: f64,
+}
+
+impl From<WindowUptime> for UptimeWindow {
+    fn from(window: WindowUptime) -> Self {
+        Self {
+            window: window.window,
+            window_secs: window.window_secs,
+            uptime_ratio: window.uptime_ratio,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct OnlineInterval {
+    start: i64,
+    end: i64,
+}
+
+impl From<Interval> for OnlineInterval {
+    fn from(interval: Interval) -> Self {
+        Self {
+            start: interval.start,
+            end: interval.end,
+        }
+    }
+}
+
+#[ComplexObject]
+impl Worker {
+    /// Uptime derived from ping arrivals
+    async fn uptime(&self, ctx: &Context<'_>) -> Option<Uptime> {
+        let state = ctx.data_unchecked::<Arc<State>>();
+        state.worker_uptime(&self.peer_id).map(|uptime| Uptime {
+            windows: uptime.windows.into_iter().map(Into::into).collect(),
+            intervals: uptime.intervals.into_iter().map(Into::into).collect(),
+        })
+    }
+}
@@ -0,0 +1,142 @@
+//! Bounded in-memory history of per-worker values sampled at a fixed resolution.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::state::WorkerInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryMetric {
+    StoredBytes,
+    MissingChunks,
+    PingRtt,
+    /// 1 if the worker answered a ping since the previous sample, 0 otherwise.
+    /// A ping in the same second as a sample counts towards both neighbouring samples.
+    PingPresence,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    timestamp: i64,
+    stored_bytes: Option<u64>,
+    missing_chunks: Option<u64>,
+    ping_rtt_secs: Option<f64>,
+    pinged: bool,
+}
+
+impl Sample {
+    fn value(&self, metric: HistoryMetric) -> Option<f64> {
+        match metric {
+            HistoryMetric::StoredBytes => self.stored_bytes.map(|v| v as f64),
+            HistoryMetric::MissingChunks => self.missing_chunks.map(|v| v as f64),
+            HistoryMetric::PingRtt => self.ping_rtt_secs.filter(|_| self.pinged),
+            HistoryMetric::PingPresence => Some(if self.pinged { 1.0 } else { 0.0 }),
+        }
+    }
+}
+
+/// A `[timestamp, value]` pair, with `null` for samples where the value was unknown
+pub type Point = (i64, Option<f64>);
+
+pub struct History {
+    capacity: usize,
+    last_sample: i64,
+    workers: HashMap<String, VecDeque<Sample>>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            last_sample: 0,
+            workers: Default::default(),
+        }
+    }
+
+    /// Appends a sample of every worker's current values
+    pub fn record(&mut self, workers: &[WorkerInfo], timestamp: i64) {
+        for worker in workers {
+            let samples = self.workers.entry(worker.peer_id.clone()).or_default();
+            samples.push_back(Sample {
+                timestamp,
+                stored_bytes: worker.stored_bytes,
+                missing_chunks: worker.missing_chunks,
+                ping_rtt_secs: worker.last_ping_rtt_secs,
+                pinged: worker.last_ping.is_some_and(|t| t >= self.last_sample),
+            });
+            while samples.len() > self.capacity {
+                samples.pop_front();
+            }
+        }
+        self.last_sample = timestamp;
+    }
+
+    pub fn remove(&mut self, peer_id: &str) {
+        self.workers.remove(peer_id);
+    }
+
+    pub fn points(&self, peer_id: &str, metric: HistoryMetric) -> Option<Vec<Point>> {
+        let samples = self.workers.get(peer_id)?;
+        Some(
+            samples
+                .iter()
+                .map(|sample| (sample.timestamp, sample.value(metric)))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(last_ping: Option<i64>, stored_bytes: u64) -> WorkerInfo {
+        WorkerInfo {
+            peer_id: "peer".to_owned(),
+            last_ping,
+            last_ping_rtt_secs: last_ping.map(|_| 0.1),
+            stored_bytes: Some(stored_bytes),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn oldest_samples_are_dropped() {
+        let mut history = History::new(2);
+        for (timestamp, stored_bytes) in [(60, 1), (120, 2), (180, 3)] {
+            history.record(&[worker(None, stored_bytes)], timestamp);
+        }
+        assert_eq!(
+            history.points("peer", HistoryMetric::StoredBytes).unwrap(),
+            [(120, Some(2.0)), (180, Some(3.0))]
+        );
+    }
+
+    #[test]
+    fn ping_presence_since_previous_sample() {
+        let mut history = History::new(10);
+        history.record(&[worker(Some(30), 0)], 60);
+        // A ping at the time of the previous sample still counts
+        history.record(&[worker(Some(60), 0)], 120);
+        history.record(&[worker(Some(60), 0)], 180);
+        assert_eq!(
+            history.points("peer", HistoryMetric::PingPresence).unwrap(),
+            [(60, Some(1.0)), (120, Some(1.0)), (180, Some(0.0))]
+        );
+        assert_eq!(
+            history.points("peer", HistoryMetric::PingRtt).unwrap(),
+            [(60, Some(0.1)), (120, Some(0.1)), (180, None)]
+        );
+    }
+
+    #[test]
+    fn unknown_worker_has_no_history() {
+        let mut history = History::new(10);
+        history.record(&[worker(None, 0)], 60);
+        history.remove("peer");
+        assert!(history.points("peer", HistoryMetric::StoredBytes).is_none());
+    }
+}
@@ -1,13 +1,80 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
+use async_graphql::http::GraphiQLSource;
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use axum::{
-    http::{header, HeaderMap},
-    response::IntoResponse,
-    routing::get,
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, FromRef, Path, Query, State,
+    },
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        Html, IntoResponse,
+    },
+    routing::{delete, get, post, put},
+    Json,
 };
+use axum_server::tls_rustls::RustlsConfig;
+use futures::StreamExt;
+use ipnet::IpNet;
+use libp2p::PeerId;
 use prometheus_client::{encoding::text::encode, registry::Registry};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, oneshot, watch,
+};
+use tokio_util::sync::CancellationToken;
+use tower_http::compression::CompressionLayer;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    cli::{HealthThresholds, RuntimeConfig, TlsPaths},
+    contract::RegisteredWorker,
+    events::ObservedEvent,
+    grafana,
+    graphql::{self, ObserverSchema},
+    history::{HistoryMetric, Point},
+    logging,
+    metrics::{now, Metrics},
+    rtt::RttStats,
+    state::{
+        HealthSnapshot, PeerIdentity, SilentWorker, State as ObserverState, VersionCount,
+        WorkerInfo, WorkerStatus, WorkerTransition,
+    },
+    transport::{Command as TransportCommand, KadBucket},
+    uptime::WorkerUptime,
+};
 
-async fn get_metrics(registry: Arc<Registry>) -> impl IntoResponse {
+#[derive(Clone, FromRef)]
+struct AppState {
+    metrics_registry: Arc<Registry>,
+    metrics: Arc<Metrics>,
+    state: Arc<ObserverState>,
+    events: broadcast::Sender<ObservedEvent>,
+    config: watch::Receiver<RuntimeConfig>,
+    health_thresholds: HealthThresholds,
+    graphql_schema: ObserverSchema,
+    transport_commands: mpsc::Sender<TransportCommand>,
+    shutdown: CancellationToken,
+    observer: ObserverInfo,
+}
+
+/// Facts about the running observer that don't change during its lifetime
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ObserverInfo {
+    pub version: String,
+    /// Unset when simulating traffic instead of joining the network
+    pub peer_id: Option<String>,
+    pub network: String,
+    pub started_at: i64,
+    pub metrics_prefix: Option<String>,
+}
+
+async fn get_metrics(State(registry): State<Arc<Registry>>) -> impl IntoResponse {
     lazy_static::lazy_static! {
         static ref HEADERS: HeaderMap = {
             let mut headers = HeaderMap::new();
@@ -27,21 +94,1145 @@ async fn get_metrics(registry: Arc<Registry>) -> impl IntoResponse {
     (HEADERS.clone(), buffer)
 }
 
+/// Records the size of the /metrics response as served, i.e. after compression
+async fn record_payload_size(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let (parts, body) = next.run(request).await.into_parts();
+    match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => {
+            metrics.metrics_payload(bytes.len());
+            axum::response::Response::from_parts(parts, Body::from(bytes))
+        }
+        Err(e) => {
+            log::warn!("Failed to encode the metrics response: {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn grafana_dashboard(
+    State(registry): State<Arc<Registry>>,
+    State(observer): State<ObserverInfo>,
+) -> Json<serde_json::Value> {
+    Json(grafana::dashboard(
+        &registry,
+        observer.metrics_prefix.as_deref(),
+        &observer.network,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum WorkerSortField {
+    #[default]
+    PeerId,
+    StoredBytes,
+    MissingChunks,
+    LastPing,
+    LastSeen,
+    PingRtt,
+    Score,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct WorkersQuery {
+    /// Only return workers with this status
+    status: Option<WorkerStatus>,
+    /// Only return workers running this version
+    version: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    sort: WorkerSortField,
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    /// Maximum number of workers to return (all if not set)
+    limit: Option<usize>,
+    /// Value of the `X-Next-Cursor` header of the previous page
+    cursor: Option<String>,
+}
+
+/// Position of a worker in the sorted list, used as the pagination cursor
+#[derive(Debug, Clone)]
+struct SortKey {
+    value: f64,
+    peer_id: String,
+}
+
+impl SortKey {
+    fn new(worker: &WorkerInfo, field: WorkerSortField) -> Self {
+        let value = match field {
+            WorkerSortField::PeerId => Some(0.0),
+            WorkerSortField::StoredBytes => worker.stored_bytes.map(|v| v as f64),
+            WorkerSortField::MissingChunks => worker.missing_chunks.map(|v| v as f64),
+            WorkerSortField::LastPing => worker.last_ping.map(|v| v as f64),
+            WorkerSortField::LastSeen => worker.last_seen.map(|v| v as f64),
+            WorkerSortField::PingRtt => worker.last_ping_rtt_secs,
+            WorkerSortField::Score => worker.score,
+        };
+        Self {
+            // Workers without a value are sorted as the smallest ones
+            value: value.unwrap_or(f64::NEG_INFINITY),
+            peer_id: worker.peer_id.clone(),
+        }
+    }
+
+    fn compare(&self, other: &Self) -> std::cmp::Ordering {
+        self.value
+            .total_cmp(&other.value)
+            .then_with(|| self.peer_id.cmp(&other.peer_id))
+    }
+
+    fn to_cursor(&self) -> String {
+        format!("{}:{}", self.value, self.peer_id)
+    }
+
+    fn from_cursor(cursor: &str) -> Option<Self> {
+        let (value, peer_id) = cursor.split_once(':')?;
+        Some(Self {
+            value: value.parse().ok()?,
+            peer_id: peer_id.to_owned(),
+        })
+    }
+}
+
+/// Known workers, filtered, sorted and paginated according to the query.
+/// Other peers, like gateways and boot nodes, aren't included.
+/// The cursor of the next page is returned in the `X-Next-Cursor` header.
+#[utoipa::path(
+    get,
+    path = "/workers",
+    tag = "workers",
+    params(WorkersQuery),
+    responses(
+        (status = 200, description = "Known workers", body = Vec<WorkerInfo>,
+            headers(("X-Next-Cursor" = String, description = "Cursor of the next page, if any"))),
+        (status = 400, description = "Invalid cursor"),
+    )
+)]
+async fn get_workers(
+    State(state): State<Arc<ObserverState>>,
+    Query(query): Query<WorkersQuery>,
+) -> Result<(HeaderMap, Json<Vec<WorkerInfo>>), StatusCode> {
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| SortKey::from_cursor(cursor).ok_or(StatusCode::BAD_REQUEST))
+        .transpose()?;
+
+    let mut workers: Vec<(SortKey, WorkerInfo)> = state
+        .workers()
+        .into_iter()
+        .filter(WorkerInfo::is_worker)
+        .filter(|w| query.status.is_none_or(|status| w.status == status))
+        .filter(|w| {
+            query
+                .version
+                .as_ref()
+                .is_none_or(|version| w.role_and_version().1 == *version)
+        })
+        .map(|w| (SortKey::new(&w, query.sort), w))
+        .filter(|(key, _)| {
+            cursor.as_ref().is_none_or(|cursor| match query.order {
+                SortOrder::Asc => key.compare(cursor).is_gt(),
+                SortOrder::Desc => key.compare(cursor).is_lt(),
+            })
+        })
+        .collect();
+    match query.order {
+        SortOrder::Asc => workers.sort_by(|(a, _), (b, _)| a.compare(b)),
+        SortOrder::Desc => workers.sort_by(|(a, _), (b, _)| b.compare(a)),
+    }
+
+    let mut headers = HeaderMap::new();
+    if let Some(limit) = query.limit {
+        if workers.len() > limit {
+            workers.truncate(limit);
+            if let Some((key, _)) = workers.last() {
+                if let Ok(value) = key.to_cursor().parse() {
+                    headers.insert("X-Next-Cursor", value);
+                }
+            }
+        }
+    }
+    Ok((headers, Json(workers.into_iter().map(|(_, w)| w).collect())))
+}
+
+#[derive(Serialize, ToSchema)]
+struct SilentWorkersResponse {
+    window_secs: u64,
+    workers: Vec<SilentWorker>,
+}
+
+/// Registered workers without a ping within the configured window
+#[utoipa::path(
+    get,
+    path = "/workers/silent",
+    tag = "workers",
+    responses((status = 200, body = SilentWorkersResponse))
+)]
+async fn get_silent_workers(
+    State(state): State<Arc<ObserverState>>,
+    State(config): State<watch::Receiver<RuntimeConfig>>,
+) -> Json<SilentWorkersResponse> {
+    let window = config.borrow().registered_silence_window;
+    Json(SilentWorkersResponse {
+        window_secs: window.as_secs(),
+        workers: state.silent_registered_workers(now() - window.as_secs() as i64),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct VersionsResponse {
+    /// Only workers that answered a ping within this time are counted
+    window_secs: u64,
+    total_workers: usize,
+    versions: Vec<VersionCount>,
+}
+
+/// Number of recently pinged workers running each version
+#[utoipa::path(
+    get,
+    path = "/versions",
+    tag = "workers",
+    responses((status = 200, body = VersionsResponse))
+)]
+async fn get_versions(
+    State(state): State<Arc<ObserverState>>,
+    State(config): State<watch::Receiver<RuntimeConfig>>,
+) -> Json<VersionsResponse> {
+    let window = config.borrow().worker_silence_threshold;
+    let versions = state.version_counts(now() - window.as_secs() as i64);
+    Json(VersionsResponse {
+        window_secs: window.as_secs(),
+        total_workers: versions.iter().map(|v| v.workers).sum(),
+        versions,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct HealthResponse {
+    healthy: bool,
+    problems: Vec<String>,
+    #[serde(flatten)]
+    health: HealthSnapshot,
+}
+
+impl IntoResponse for HealthResponse {
+    fn into_response(self) -> axum::response::Response {
+        let status = if self.healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+fn health_response(health: HealthSnapshot, problems: Vec<String>) -> HealthResponse {
+    HealthResponse {
+        healthy: problems.is_empty(),
+        problems,
+        health,
+    }
+}
+
+fn is_older_than(timestamp: Option<i64>, max_age: Duration) -> bool {
+    timestamp.is_none_or(|t| now() - t > max_age.as_secs() as i64)
+}
+
+/// Liveness: the event loop keeps iterating
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Live", body = HealthResponse),
+        (status = 503, description = "Not live", body = HealthResponse),
+    )
+)]
+async fn healthz(
+    State(state): State<Arc<ObserverState>>,
+    State(thresholds): State<HealthThresholds>,
+) -> HealthResponse {
+    let health = state.health();
+    let mut problems = Vec::new();
+    if is_older_than(health.last_loop_tick, thresholds.max_loop_stall) {
+        problems.push("event loop is stalled".to_owned());
+    }
+    health_response(health, problems)
+}
+
+/// Readiness: connected to the network and receiving events from it
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready", body = HealthResponse),
+        (status = 503, description = "Not ready", body = HealthResponse),
+    )
+)]
+async fn readyz(
+    State(state): State<Arc<ObserverState>>,
+    State(thresholds): State<HealthThresholds>,
+) -> HealthResponse {
+    let health = state.health();
+    let mut problems = Vec::new();
+    if is_older_than(health.last_loop_tick, thresholds.max_loop_stall) {
+        problems.push("event loop is stalled".to_owned());
+    }
+    if health.connected_boot_nodes == 0 {
+        problems.push("no boot node connected".to_owned());
+    }
+    if is_older_than(health.last_event, thresholds.max_event_age) {
+        problems.push("no recent network events".to_owned());
+    }
+    health_response(health, problems)
+}
+
+/// Everything known about a single worker
+#[derive(Serialize, ToSchema)]
+struct WorkerDetails {
+    #[serde(flatten)]
+    info: WorkerInfo,
+    role: String,
+    /// Version part of the agent string
+    role_version: String,
+    uptime: Option<WorkerUptime>,
+    rtt: Option<RttStats>,
+    registration: Option<RegisteredWorker>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/workers/{peer_id}",
+    tag = "workers",
+    params(("peer_id" = String, Path, description = "Peer ID of the worker")),
+    responses(
+        (status = 200, body = WorkerDetails),
+        (status = 404, description = "The worker isn't known"),
+    )
+)]
+async fn get_worker(
+    State(state): State<Arc<ObserverState>>,
+    Path(peer_id): Path<String>,
+) -> Result<Json<WorkerDetails>, StatusCode> {
+    let info = state.worker(&peer_id).ok_or(StatusCode::NOT_FOUND)?;
+    let (role, role_version) = info.role_and_version();
+    Ok(Json(WorkerDetails {
+        role,
+        role_version,
+        uptime: state.worker_uptime(&peer_id),
+        rtt: state.rtt_stats(&peer_id),
+        registration: state.registered_worker(&peer_id),
+        info,
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    #[param(inline)]
+    metric: HistoryMetric,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HistoryResponse {
+    peer_id: String,
+    metric: HistoryMetric,
+    /// `[timestamp, value]` pairs, oldest first. The value is null when it was unknown.
+    #[schema(value_type = Vec<Vec<f64>>)]
+    points: Vec<Point>,
+}
+
+/// Recent values of a per-worker series at the configured resolution
+#[utoipa::path(
+    get,
+    path = "/workers/{peer_id}/history",
+    tag = "workers",
+    params(("peer_id" = String, Path, description = "Peer ID of the worker"), HistoryQuery),
+    responses(
+        (status = 200, body = HistoryResponse),
+        (status = 404, description = "No history of the worker was recorded"),
+    )
+)]
+async fn get_worker_history(
+    State(state): State<Arc<ObserverState>>,
+    Path(peer_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<HistoryResponse>, StatusCode> {
+    let points = state
+        .history(&peer_id, query.metric)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(HistoryResponse {
+        peer_id,
+        metric: query.metric,
+        points,
+    }))
+}
+
+/// Status returned by a worker in response to an on-demand probe
+#[derive(Serialize, ToSchema)]
+struct ProbeResponse {
+    peer_id: String,
+    duration_secs: f64,
+    assignment_id: String,
+    stored_bytes: Option<u64>,
+    missing_chunks: Option<u64>,
+    /// Debug representation of the whole decoded response
+    raw: String,
+}
+
+/// Requests the current status of a worker over the status protocol
+#[utoipa::path(
+    post,
+    path = "/workers/{peer_id}/probe",
+    tag = "workers",
+    params(("peer_id" = String, Path, description = "Peer ID of the worker")),
+    responses(
+        (status = 200, body = ProbeResponse),
+        (status = 400, description = "Invalid peer ID"),
+        (status = 502, description = "The worker didn't answer", body = String),
+    )
+)]
+async fn probe_worker(
+    State(commands): State<mpsc::Sender<TransportCommand>>,
+    Path(peer_id): Path<String>,
+) -> Result<Json<ProbeResponse>, (StatusCode, String)> {
+    let peer: PeerId = peer_id
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid peer ID: {e}")))?;
+    let response = ask_transport(&commands, |reply| {
+        TransportCommand::RequestStatus(peer, reply)
+    })
+    .await?
+    .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+    let heartbeat = response.heartbeat;
+    Ok(Json(ProbeResponse {
+        peer_id,
+        duration_secs: response.duration.as_secs_f64(),
+        raw: format!("{heartbeat:?}"),
+        missing_chunks: heartbeat.missing_chunks.as_ref().map(|b| b.ones()),
+        stored_bytes: heartbeat.stored_bytes,
+        assignment_id: heartbeat.assignment_id,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct WorkerSnapshot {
+    #[serde(flatten)]
+    worker: WorkerInfo,
+    rtt: Option<RttStats>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Snapshot {
+    taken_at: i64,
+    uptime_secs: i64,
+    observer: ObserverInfo,
+    health: HealthSnapshot,
+    workers: Vec<WorkerSnapshot>,
+    peers: Vec<PeerIdentity>,
+}
+
+/// Everything the observer currently knows, as a single document for archival and support
+#[utoipa::path(
+    get,
+    path = "/snapshot",
+    tag = "observer",
+    responses((status = 200, body = Snapshot))
+)]
+async fn get_snapshot(
+    State(state): State<Arc<ObserverState>>,
+    State(observer): State<ObserverInfo>,
+) -> Json<Snapshot> {
+    let taken_at = now();
+    let workers = state
+        .workers()
+        .into_iter()
+        .map(|worker| WorkerSnapshot {
+            rtt: state.rtt_stats(&worker.peer_id),
+            worker,
+        })
+        .collect();
+    Json(Snapshot {
+        taken_at,
+        uptime_secs: taken_at - observer.started_at,
+        observer,
+        health: state.health(),
+        workers,
+        peers: state.identities(),
+    })
+}
+
+/// Identify information of all known peers
+#[utoipa::path(
+    get,
+    path = "/peers",
+    tag = "peers",
+    responses((status = 200, body = Vec<PeerIdentity>))
+)]
+async fn get_peers(State(state): State<Arc<ObserverState>>) -> Json<Vec<PeerIdentity>> {
+    Json(state.identities())
+}
+
+#[utoipa::path(
+    get,
+    path = "/peers/{peer_id}",
+    tag = "peers",
+    params(("peer_id" = String, Path, description = "Peer ID")),
+    responses(
+        (status = 200, body = PeerIdentity),
+        (status = 404, description = "The peer hasn't been identified"),
+    )
+)]
+async fn get_peer(
+    State(state): State<Arc<ObserverState>>,
+    Path(peer_id): Path<String>,
+) -> Result<Json<PeerIdentity>, StatusCode> {
+    state
+        .identity(&peer_id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Sends a command to the transport loop and waits for its reply
+async fn ask_transport<T>(
+    commands: &mpsc::Sender<TransportCommand>,
+    command: impl FnOnce(oneshot::Sender<T>) -> TransportCommand,
+) -> Result<T, (StatusCode, String)> {
+    let unavailable = || {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Transport is not running".to_owned(),
+        )
+    };
+    let (reply_tx, reply_rx) = oneshot::channel();
+    commands
+        .send(command(reply_tx))
+        .await
+        .map_err(|_| unavailable())?;
+    reply_rx.await.map_err(|_| unavailable())
+}
+
+/// Dumps the Kademlia routing table of the observer
+async fn debug_kad(
+    State(commands): State<mpsc::Sender<TransportCommand>>,
+) -> Result<Json<Vec<KadBucket>>, (StatusCode, String)> {
+    let buckets = ask_transport(&commands, TransportCommand::DumpRoutingTable).await?;
+    Ok(Json(buckets))
+}
+
+fn peer_ids_to_strings(peers: Vec<PeerId>) -> Vec<String> {
+    peers.iter().map(|peer_id| peer_id.to_string()).collect()
+}
+
+/// Peers whose events are ignored and that are never dialed
+#[utoipa::path(
+    get,
+    path = "/admin/blocked-peers",
+    tag = "admin",
+    responses((status = 200, body = Vec<String>))
+)]
+async fn get_blocked_peers(
+    State(commands): State<mpsc::Sender<TransportCommand>>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let peers = ask_transport(&commands, TransportCommand::BlockedPeers).await?;
+    Ok(Json(peer_ids_to_strings(peers)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/blocked-peers/{peer_id}",
+    tag = "admin",
+    params(("peer_id" = String, Path, description = "Peer ID to block")),
+    responses(
+        (status = 200, description = "All blocked peers", body = Vec<String>),
+        (status = 400, description = "Invalid peer ID"),
+    )
+)]
+async fn block_peer(
+    State(commands): State<mpsc::Sender<TransportCommand>>,
+    Path(peer_id): Path<String>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    set_blocked(commands, peer_id, true).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/blocked-peers/{peer_id}",
+    tag = "admin",
+    params(("peer_id" = String, Path, description = "Peer ID to unblock")),
+    responses(
+        (status = 200, description = "All blocked peers", body = Vec<String>),
+        (status = 400, description = "Invalid peer ID"),
+    )
+)]
+async fn unblock_peer(
+    State(commands): State<mpsc::Sender<TransportCommand>>,
+    Path(peer_id): Path<String>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    set_blocked(commands, peer_id, false).await
+}
+
+async fn set_blocked(
+    commands: mpsc::Sender<TransportCommand>,
+    peer_id: String,
+    blocked: bool,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let peer_id: PeerId = peer_id
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid peer ID: {e}")))?;
+    let peers = ask_transport(&commands, |reply| {
+        TransportCommand::SetBlocked(peer_id, blocked, reply)
+    })
+    .await?;
+    Ok(Json(peer_ids_to_strings(peers)))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct LogLevel {
+    /// Log filter directives in env_logger syntax, e.g. `info,libp2p_kad=debug`
+    filter: String,
+}
+
+/// Log filter currently in effect
+#[utoipa::path(
+    get,
+    path = "/admin/log-level",
+    tag = "admin",
+    responses((status = 200, body = LogLevel))
+)]
+async fn get_log_level() -> Json<LogLevel> {
+    Json(LogLevel {
+        filter: logging::filter(),
+    })
+}
+
+/// Replaces the log filter until the next restart or configuration reload
+#[utoipa::path(
+    put,
+    path = "/admin/log-level",
+    tag = "admin",
+    request_body = LogLevel,
+    responses(
+        (status = 200, description = "The new filter", body = LogLevel),
+        (status = 400, description = "Invalid filter"),
+    )
+)]
+async fn set_log_level(
+    Json(level): Json<LogLevel>,
+) -> Result<Json<LogLevel>, (StatusCode, String)> {
+    logging::validate_filter(&level.filter)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid log filter: {e}")))?;
+    logging::set_filter(&level.filter);
+    log::info!("Log filter set to {}", level.filter);
+    Ok(Json(level))
+}
+
+#[derive(Deserialize, IntoParams)]
+struct DeleteMetricsParams {
+    /// Peer ID of the worker whose series to remove
+    peer_id: String,
+}
+
+/// Removes all metric series and the state of a worker, e.g. after it was decommissioned
+#[utoipa::path(
+    delete,
+    path = "/admin/metrics",
+    tag = "admin",
+    params(DeleteMetricsParams),
+    responses(
+        (status = 204, description = "Series removed"),
+        (status = 404, description = "No series of the worker exist"),
+    )
+)]
+async fn delete_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    State(state): State<Arc<ObserverState>>,
+    Query(params): Query<DeleteMetricsParams>,
+) -> StatusCode {
+    if !metrics.has_peer(&params.peer_id) {
+        return StatusCode::NOT_FOUND;
+    }
+    // Otherwise the ratios of the worker would be exported again on the next update
+    state.remove_worker(&params.peer_id);
+    metrics.remove_peer(&params.peer_id);
+    log::info!("Removed the metrics of worker {}", params.peer_id);
+    StatusCode::NO_CONTENT
+}
+
+/// Removes the series and the state of all peers, which reappear as they report again
+#[utoipa::path(
+    post,
+    path = "/admin/metrics/reset",
+    tag = "admin",
+    responses((status = 200, description = "Number of peers whose series were removed", body = usize))
+)]
+async fn reset_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    State(state): State<Arc<ObserverState>>,
+) -> Json<usize> {
+    // Like for a single worker, the ratios and scores would be exported again otherwise
+    state.remove_all_workers();
+    let removed = metrics.reset_peers();
+    log::info!("Removed the metrics of {removed} peers");
+    Json(removed)
+}
+
+/// Uptime ratios and online intervals of a worker derived from its pings
+#[utoipa::path(
+    get,
+    path = "/workers/{peer_id}/uptime",
+    tag = "workers",
+    params(("peer_id" = String, Path, description = "Peer ID of the worker")),
+    responses(
+        (status = 200, body = WorkerUptime),
+        (status = 404, description = "No pings from the worker were observed"),
+    )
+)]
+async fn get_worker_uptime(
+    State(state): State<Arc<ObserverState>>,
+    Path(peer_id): Path<String>,
+) -> Result<Json<WorkerUptime>, StatusCode> {
+    state
+        .worker_uptime(&peer_id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn graphql_handler(
+    State(schema): State<ObserverSchema>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    schema.execute(request.into_inner()).await.into()
+}
+
+async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+/// Comma-separated lists of event types and peer IDs to receive. Empty means all.
+#[derive(Deserialize)]
+struct EventsFilter {
+    #[serde(rename = "type")]
+    event_types: Option<String>,
+    peer_id: Option<String>,
+}
+
+impl EventsFilter {
+    fn matches(&self, event: &ObservedEvent) -> bool {
+        let matches_list = |list: &Option<String>, value: &str| {
+            list.as_ref()
+                .is_none_or(|list| list.split(',').any(|item| item == value))
+        };
+        matches_list(&self.event_types, event.kind.type_name())
+            && matches_list(&self.peer_id, event.kind.peer_id())
+    }
+}
+
+async fn events_ws(
+    ws: WebSocketUpgrade,
+    Query(filter): Query<EventsFilter>,
+    State(events): State<broadcast::Sender<ObservedEvent>>,
+    State(shutdown): State<CancellationToken>,
+) -> impl IntoResponse {
+    let events = events.subscribe();
+    ws.on_upgrade(move |socket| stream_events(socket, events, filter, shutdown))
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<ObservedEvent>,
+    filter: EventsFilter,
+    shutdown: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if !filter.matches(&event) {
+                        continue;
+                    }
+                    let text =
+                        serde_json::to_string(&event).expect("Events should be serializable");
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    log::debug!("WebSocket client is lagging behind, {n} events dropped");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn worker_transitions(
+    State(state): State<Arc<ObserverState>>,
+    State(shutdown): State<CancellationToken>,
+) -> impl IntoResponse {
+    let stream = futures::stream::unfold(state.subscribe_transitions(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(transition) => return Some((transition_event(&transition), rx)),
+                Err(RecvError::Lagged(n)) => {
+                    log::debug!("SSE client is lagging behind, {n} transitions dropped");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    // Long-lived streams would otherwise hold back the graceful shutdown
+    .take_until(shutdown.cancelled_owned());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn transition_event(transition: &WorkerTransition) -> Result<SseEvent, axum::Error> {
+    SseEvent::default()
+        .event(transition.name())
+        .json_data(transition)
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Network observer"),
+    paths(
+        healthz,
+        readyz,
+        get_workers,
+        get_silent_workers,
+        get_worker,
+        get_worker_uptime,
+        get_worker_history,
+        probe_worker,
+        get_versions,
+        get_peers,
+        get_peer,
+        get_snapshot,
+        get_blocked_peers,
+        block_peer,
+        unblock_peer,
+        get_log_level,
+        set_log_level,
+        delete_metrics,
+        reset_metrics
+    )
+)]
+struct ApiDoc;
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+async fn require_token(
+    State(token): State<Arc<str>>,
+    request: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response(),
+    }
+}
+
+async fn require_allowed_source(
+    State((allowed, metrics)): State<(Arc<[IpNet]>, Arc<Metrics>)>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let ip = source.ip().to_canonical();
+    if allowed.iter().any(|net| net.contains(&ip)) {
+        next.run(request).await
+    } else {
+        metrics.http_request_rejected();
+        StatusCode::FORBIDDEN.into_response()
+    }
+}
+
+/// Compares the token without returning early, so response timing doesn't leak its prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Everything the HTTP server needs from the rest of the observer
+pub struct ServerConfig {
+    pub metrics_registry: Arc<Registry>,
+    pub metrics: Arc<Metrics>,
+    pub state: Arc<ObserverState>,
+    pub events: broadcast::Sender<ObservedEvent>,
+    pub config: watch::Receiver<RuntimeConfig>,
+    pub transport_commands: mpsc::Sender<TransportCommand>,
+    pub health_thresholds: HealthThresholds,
+    pub auth_token: Option<String>,
+    pub allowed_networks: Vec<IpNet>,
+    pub shutdown: CancellationToken,
+    pub observer: ObserverInfo,
+}
+
 pub struct Server {
     router: axum::Router,
+    shutdown: CancellationToken,
 }
 
 impl Server {
-    pub fn new(metrics_registry: Registry) -> Self {
-        let metrics_registry = Arc::new(metrics_registry);
-        let router =
-            axum::Router::new().route("/metrics", get(move || get_metrics(metrics_registry)));
-        Self { router }
+    pub fn new(
+        ServerConfig {
+            metrics_registry,
+            metrics,
+            state,
+            events,
+            config,
+            transport_commands,
+            health_thresholds,
+            auth_token,
+            allowed_networks,
+            shutdown,
+            observer,
+        }: ServerConfig,
+    ) -> Self {
+        let graphql_schema = graphql::schema(state.clone());
+        let health = axum::Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz));
+        let mut api = axum::Router::new()
+            // Compressed according to the Accept-Encoding header
+            .route(
+                "/metrics",
+                get(get_metrics)
+                    .layer(CompressionLayer::new().gzip(true).deflate(true))
+                    .layer(middleware::from_fn_with_state(
+                        metrics.clone(),
+                        record_payload_size,
+                    )),
+            )
+            .route("/grafana/dashboard.json", get(grafana_dashboard))
+            .route("/workers", get(get_workers))
+            .route("/workers/silent", get(get_silent_workers))
+            .route("/workers/:peer_id", get(get_worker))
+            .route("/workers/:peer_id/uptime", get(get_worker_uptime))
+            .route("/workers/:peer_id/history", get(get_worker_history))
+            .route("/workers/:peer_id/probe", post(probe_worker))
+            .route("/debug/kad", get(debug_kad))
+            .route("/versions", get(get_versions))
+            .route("/peers", get(get_peers))
+            .route("/peers/:peer_id", get(get_peer))
+            .route("/snapshot", get(get_snapshot))
+            .route("/openapi.json", get(openapi))
+            .route("/graphql", get(graphiql).post(graphql_handler))
+            .route("/events/workers", get(worker_transitions))
+            .route("/ws/events", get(events_ws));
+        if let Some(token) = auth_token {
+            // Admin endpoints change what every client sees, so they never run unauthenticated
+            let admin = axum::Router::new()
+                .route("/admin/blocked-peers", get(get_blocked_peers))
+                .route(
+                    "/admin/blocked-peers/:peer_id",
+                    put(block_peer).delete(unblock_peer),
+                )
+                .route("/admin/log-level", get(get_log_level).put(set_log_level))
+                .route("/admin/metrics", delete(delete_metrics))
+                .route("/admin/metrics/reset", post(reset_metrics));
+            api = api.merge(admin).route_layer(middleware::from_fn_with_state(
+                Arc::<str>::from(token),
+                require_token,
+            ));
+        } else {
+            log::info!("Admin endpoints are disabled because --http-auth-token isn't set");
+        }
+        // Added last so that it runs first and rejected sources never reach the token check
+        if !allowed_networks.is_empty() {
+            api = api.route_layer(middleware::from_fn_with_state(
+                (Arc::<[IpNet]>::from(allowed_networks), metrics.clone()),
+                require_allowed_source,
+            ));
+        }
+        let router = health.merge(api).with_state(AppState {
+            metrics_registry,
+            metrics,
+            state,
+            events,
+            config,
+            health_thresholds,
+            graphql_schema,
+            transport_commands,
+            shutdown: shutdown.clone(),
+            observer,
+        });
+        Self { router, shutdown }
     }
 
-    pub async fn run(self, port: u16) -> anyhow::Result<()> {
-        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
-        axum::serve(listener, self.router).await?;
+    /// Serves requests until shutdown, then gives in-flight requests `drain_timeout` to complete
+    pub async fn run(
+        self,
+        addr: SocketAddr,
+        tls: Option<TlsPaths>,
+        drain_timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let Some(tls) = tls else {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            log::info!("HTTP server listening on {addr}");
+            let serve = axum::serve(
+                listener,
+                self.router
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(self.shutdown.clone().cancelled_owned());
+            let deadline = async {
+                self.shutdown.cancelled().await;
+                tokio::time::sleep(drain_timeout).await;
+            };
+            tokio::select! {
+                result = serve => result?,
+                _ = deadline => log::warn!("HTTP requests didn't finish in {drain_timeout:?}, dropping them"),
+            }
+            return Ok(());
+        };
+
+        let config = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+        #[cfg(unix)]
+        tokio::spawn(reload_tls_on_sighup(config.clone(), tls));
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            let shutdown = self.shutdown.clone();
+            async move {
+                shutdown.cancelled().await;
+                handle.graceful_shutdown(Some(drain_timeout));
+            }
+        });
+        log::info!("HTTPS server listening on {addr}");
+        axum_server::bind_rustls(addr, config)
+            .handle(handle)
+            .serve(
+                self.router
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?;
         Ok(())
     }
 }
+
+#[cfg(unix)]
+async fn reload_tls_on_sighup(config: RustlsConfig, tls: TlsPaths) -> anyhow::Result<()> {
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        match config.reload_from_pem_file(&tls.cert, &tls.key).await {
+            Ok(()) => log::info!("Reloaded TLS certificate"),
+            Err(e) => log::error!("Failed to reload TLS certificate: {e:?}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(value: f64, peer_id: &str) -> SortKey {
+        SortKey {
+            value,
+            peer_id: peer_id.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reset_metrics_removes_derived_series() {
+        const PEER_ID: &str = "12D3KooWBwbQFT48cNYGPbDwm8rjasbZkc1VMo6rCR6217qr165S";
+        let args = crate::testing::default_args();
+        let state = Arc::new(crate::new_state(&args));
+        let metrics = Arc::new(Metrics::default());
+        let mut registry = crate::metrics::new_registry(None, &[]);
+        metrics.register(&mut registry);
+        let ping = ObservedEvent {
+            timestamp: now(),
+            kind: crate::events::EventKind::Ping {
+                peer_id: PEER_ID.to_owned(),
+                rtt_secs: Some(0.05),
+                error: None,
+            },
+        };
+        crate::handle_event(&state, &metrics, &ping);
+        let weights = args.score_weights();
+        crate::update_ratios(&state, &metrics, &weights, now());
+        let series = |registry: &Registry| {
+            let mut buffer = String::new();
+            encode(&mut buffer, registry).unwrap();
+            buffer.matches(PEER_ID).count()
+        };
+        assert!(series(&registry) > 0);
+
+        let Json(removed) = reset_metrics(State(metrics.clone()), State(state.clone())).await;
+        assert_eq!(removed, 1);
+        crate::update_ratios(&state, &metrics, &weights, now());
+        assert_eq!(series(&registry), 0);
+        assert!(state.worker(PEER_ID).is_none());
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        for key in [
+            key(
+                0.1 + 0.2,
+                "12D3KooWBwbQFT48cNYGPbDwm8rjasbZkc1VMo6rCR6217qr165S",
+            ),
+            key(1_700_000_000.0, "a"),
+            key(f64::NEG_INFINITY, "b"),
+        ] {
+            let parsed = SortKey::from_cursor(&key.to_cursor()).unwrap();
+            assert!(parsed.compare(&key).is_eq(), "{key:?} != {parsed:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        assert!(SortKey::from_cursor("no-separator").is_none());
+        assert!(SortKey::from_cursor("abc:peer").is_none());
+    }
+
+    #[test]
+    fn keys_are_ordered_by_value_then_peer_id() {
+        let worker = WorkerInfo {
+            peer_id: "a".to_owned(),
+            ..Default::default()
+        };
+        let missing = SortKey::new(&worker, WorkerSortField::StoredBytes);
+        assert!(missing.compare(&key(0.0, "a")).is_lt());
+        assert!(key(1.0, "b").compare(&key(1.0, "c")).is_lt());
+        assert!(key(2.0, "a").compare(&key(1.0, "c")).is_gt());
+    }
+}
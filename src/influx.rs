@@ -0,0 +1,78 @@
+//! Pushes all registered series to InfluxDB v2 in the line protocol.
+
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use prometheus_client::registry::Registry;
+
+use crate::{cli::InfluxArgs, metrics::now, openmetrics};
+
+/// Periodically writes the current value of every series to the configured bucket
+pub async fn run(client: reqwest::Client, registry: Arc<Registry>, args: InfluxArgs, url: String) {
+    let write_url = format!("{}/api/v2/write", url.trim_end_matches('/'));
+    let mut interval = tokio::time::interval(Duration::from_secs(args.influx_interval_sec));
+    loop {
+        interval.tick().await;
+        let body = line_protocol(&registry);
+        match push(&client, &write_url, &args, body).await {
+            Ok(()) => log::debug!("Pushed metrics to InfluxDB at {url}"),
+            Err(e) => log::warn!("Failed to push metrics to InfluxDB at {url}: {e:?}"),
+        }
+    }
+}
+
+/// Renders each sample as `<name>,<labels> value=<value> <timestamp>`
+fn line_protocol(registry: &Registry) -> String {
+    let timestamp = now();
+    let mut body = String::new();
+    for sample in openmetrics::collect(registry)
+        .into_iter()
+        .flat_map(|family| family.samples)
+    {
+        // InfluxDB rejects non-finite field values
+        if !sample.value.is_finite() {
+            continue;
+        }
+        body.push_str(&escape(&sample.name));
+        for (name, value) in &sample.labels {
+            if !value.is_empty() {
+                write!(body, ",{}={}", escape(name), escape(value)).unwrap();
+            }
+        }
+        writeln!(body, " value={} {timestamp}", sample.value).unwrap();
+    }
+    body
+}
+
+/// Escapes the characters that are special in measurement names and tags
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+        .replace('\n', "\\n")
+}
+
+async fn push(client: &reqwest::Client, url: &str, args: &InfluxArgs, body: String) -> Result<()> {
+    let response = client
+        .post(url)
+        .query(&[
+            ("org", args.influx_org.as_deref().unwrap_or_default()),
+            ("bucket", args.influx_bucket.as_deref().unwrap_or_default()),
+            ("precision", "s"),
+        ])
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("Token {}", args.influx_token.as_deref().unwrap_or_default()),
+        )
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        anyhow::bail!("InfluxDB returned {status}: {text}");
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Publishes observed events to Kafka as JSON messages keyed by peer ID.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord, Producer},
+    ClientConfig,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{cli::KafkaArgs, events::ObservedEvent};
+
+pub struct KafkaSink {
+    producer: FutureProducer,
+    args: KafkaArgs,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, args: KafkaArgs) -> Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set(
+                "message.timeout.ms",
+                (args.kafka_message_timeout_sec * 1000).to_string(),
+            )
+            .set("compression.type", "lz4")
+            .create()?;
+        Ok(Self { producer, args })
+    }
+
+    pub async fn run(self, mut events: broadcast::Receiver<ObservedEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => self.publish(event),
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Kafka sink is lagging behind, {n} events dropped");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+        if let Err(e) = self.producer.flush(Duration::from_secs(10)) {
+            log::warn!("Failed to flush Kafka producer: {e}");
+        }
+    }
+
+    fn publish(&self, event: ObservedEvent) {
+        if !self.args.kafka_event_types.is_empty()
+            && !self
+                .args
+                .kafka_event_types
+                .iter()
+                .any(|t| t == event.kind.type_name())
+        {
+            return;
+        }
+        let topic = format!("{}{}", self.args.kafka_topic_prefix, event.kind.type_name());
+        let payload = serde_json::to_vec(&event).expect("Events should be serializable");
+        let record = FutureRecord::to(&topic)
+            .key(event.kind.peer_id())
+            .payload(&payload);
+        // Only enqueue here so that a slow broker doesn't stall the sink on every message
+        match self.producer.send_result(record) {
+            Ok(delivery) => {
+                tokio::spawn(async move {
+                    match delivery.await {
+                        Ok(Ok(_)) => {}
+                        Ok(Err((e, _))) => log::warn!("Failed to deliver event to Kafka: {e}"),
+                        Err(_) => log::warn!("Kafka delivery was cancelled"),
+                    }
+                });
+            }
+            Err((e, _)) => log::warn!("Failed to enqueue event for Kafka: {e}"),
+        }
+    }
+}
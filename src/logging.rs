@@ -0,0 +1,91 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use lazy_static::lazy_static;
+use log::{Log, Metadata, Record};
+
+use crate::cli::LogFormat;
+
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref LOGGER: RwLock<env_logger::Logger> = RwLock::new(build_logger("info"));
+    static ref FILTER: RwLock<String> = RwLock::new("info".to_owned());
+}
+
+/// Delegates to the current env_logger instance, which can be replaced at runtime
+struct ReloadableLogger;
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        LOGGER.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        LOGGER.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        LOGGER.read().unwrap().flush()
+    }
+}
+
+pub fn setup_logging(filter: &str, format: LogFormat, with_sentry: bool) {
+    JSON_FORMAT.store(format == LogFormat::Json, Ordering::Relaxed);
+    set_filter(filter);
+    if with_sentry {
+        // Errors are reported as Sentry events, lower levels are attached as breadcrumbs
+        let logger = sentry::integrations::log::SentryLogger::with_dest(ReloadableLogger);
+        log::set_boxed_logger(Box::new(logger)).expect("Logger should only be set once");
+    } else {
+        log::set_boxed_logger(Box::new(ReloadableLogger)).expect("Logger should only be set once");
+    }
+}
+
+/// Replaces the log filter (in `RUST_LOG` syntax) of the running logger
+pub fn set_filter(filter: &str) {
+    let logger = build_logger(filter);
+    log::set_max_level(logger.filter());
+    *LOGGER.write().unwrap() = logger;
+    *FILTER.write().unwrap() = filter.to_owned();
+}
+
+/// Checks the filter with env_logger's own parser, which otherwise only prints a warning for invalid directives
+pub fn validate_filter(filter: &str) -> Result<(), env_logger::filter::ParseError> {
+    env_logger::filter::Builder::new().try_parse(filter)?;
+    Ok(())
+}
+
+/// Returns the log filter currently in effect
+pub fn filter() -> String {
+    FILTER.read().unwrap().clone()
+}
+
+fn build_logger(filter: &str) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(filter);
+    if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+        builder.parse_write_style(&style);
+    }
+    if JSON_FORMAT.load(Ordering::Relaxed) {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": chrono::Utc::now()
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "module_path": record.module_path(),
+                "file": record.file(),
+                "line": record.line(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    builder.build()
+}
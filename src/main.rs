@@ -1,12 +1,54 @@
-use clap::Parser;
-use env_logger::Env;
+use std::{
+    collections::HashMap,
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use futures::StreamExt;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_util::sync::CancellationToken;
 
+use events::{EventKind, ObservedEvent};
+
+mod address_book;
+mod alerts;
+mod check;
 mod cli;
+mod clickhouse;
+mod contract;
+mod events;
+mod geoip;
+mod grafana;
+mod graphite;
+mod graphql;
+mod history;
 mod http_server;
+mod influx;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod logging;
 mod metrics;
+mod nats;
+mod openmetrics;
+mod otlp;
+mod process;
+mod rate_limit;
+mod recording;
+mod remote_write;
+mod report;
+mod rtt;
+mod score;
+mod simulation;
+mod state;
+mod statsd;
+mod status_protocol;
+mod success_ratio;
+#[cfg(test)]
+mod testing;
+mod textfile;
 mod transport;
+mod uptime;
 
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
@@ -15,86 +57,747 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+const EVENTS_CHANNEL_CAPACITY: usize = 4096;
+/// Events received while this many are waiting to be handled are dropped
+const EVENT_QUEUE_CAPACITY: usize = 4096;
+const TRANSPORT_COMMANDS_CHANNEL_CAPACITY: usize = 64;
+const EXPIRATION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const SILENCE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const LOOP_TICK_INTERVAL: Duration = Duration::from_secs(5);
+const RATIO_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+const PEER_COUNT_INTERVAL: Duration = Duration::from_secs(30);
+const GEOIP_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+const PROCESS_STATS_INTERVAL: Duration = Duration::from_secs(15);
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-    let args = cli::Cli::parse();
+async fn main() -> anyhow::Result<ExitCode> {
+    let mut args = cli::Cli::load()?;
+    if let Some(command) = args
+        .command
+        .take()
+        .filter(|command| !matches!(command, cli::Command::Run))
+    {
+        return run_command(command, &args)
+            .await
+            .map(|()| ExitCode::SUCCESS);
+    }
+    let network_name = network_name(args.network);
 
-    let mut registry = prometheus_client::registry::Registry::default();
-    metrics::register_metrics(&mut registry);
-    let libp2p_metrics = libp2p::metrics::Metrics::new(&mut registry);
+    let _sentry_guard = args.sentry_dsn.as_ref().map(|dsn| {
+        let environment = args
+            .sentry_environment
+            .clone()
+            .unwrap_or_else(|| network_name.clone());
+        sentry::init((
+            dsn.as_str(),
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                environment: Some(environment.into()),
+                ..Default::default()
+            },
+        ))
+    });
+    #[cfg(feature = "tokio-console")]
+    if args.tokio_console {
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(args.tokio_console_addr)
+            .init();
+    }
+    logging::setup_logging(&args.log_filter, args.log_format, args.sentry_dsn.is_some());
 
-    tokio::spawn(http_server::Server::new(registry).run(args.port));
+    let mut registry = metrics::new_registry(args.metrics_prefix.as_deref(), &args.metrics_labels);
+    let metrics = Arc::new(metrics::Metrics::default());
+    metrics.set_network_name(network_name);
+    metrics.register(&mut registry);
 
-    let network_name = match args.network {
-        sqd_contract_client::Network::Tethys => "testnet".to_owned(),
-        sqd_contract_client::Network::Mainnet => "mainnet".to_owned(),
+    let address_book = args
+        .address_book
+        .as_deref()
+        .map(address_book::AddressBook::load)
+        .transpose()?;
+    let known_addresses = address_book
+        .as_ref()
+        .map(|book| book.addresses())
+        .unwrap_or_default();
+    let transport = match args.simulation.simulate {
+        Some(_) => None,
+        None => Some(
+            transport::Transport::build(&args, &mut registry, metrics.clone(), known_addresses)
+                .await?,
+        ),
     };
-    let transport = transport::Transport::build(args, libp2p_metrics).await?;
-    tokio::spawn(run_transport(transport, network_name));
+    let registry = Arc::new(registry);
 
-    tokio::signal::ctrl_c().await?;
+    let state = Arc::new(new_state(&args));
+    let (events_tx, _) = broadcast::channel(EVENTS_CHANNEL_CAPACITY);
+    let (commands_tx, commands_rx) = mpsc::channel(TRANSPORT_COMMANDS_CHANNEL_CAPACITY);
+    let (config_tx, config_rx) = watch::channel(args.runtime_config()?);
+    #[cfg(unix)]
+    tokio::spawn(reload_config_on_sighup(config_tx));
+    #[cfg(not(unix))]
+    drop(config_tx);
+
+    let observer_info = http_server::ObserverInfo {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        peer_id: transport.as_ref().map(|t| t.local_peer_id().to_string()),
+        network: network_name(args.network),
+        started_at: metrics::now(),
+        metrics_prefix: args.metrics_prefix.clone(),
+    };
+    let shutdown = CancellationToken::new();
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout_sec);
+    let http_server = tokio::spawn(
+        http_server::Server::new(http_server::ServerConfig {
+            metrics_registry: registry.clone(),
+            metrics: metrics.clone(),
+            state: state.clone(),
+            events: events_tx.clone(),
+            config: config_rx.clone(),
+            transport_commands: commands_tx.clone(),
+            health_thresholds: args.health_thresholds(),
+            auth_token: args.http_auth_token.clone(),
+            allowed_networks: args.http_allow_cidr.clone(),
+            shutdown: shutdown.clone(),
+            observer: observer_info,
+        })
+        .run(args.http_addr(), args.tls(), shutdown_timeout),
+    );
+
+    tokio::spawn(expire_workers(
+        state.clone(),
+        metrics.clone(),
+        config_rx.clone(),
+        commands_tx.clone(),
+    ));
+    tokio::spawn(detect_silent_workers(state.clone(), config_rx.clone()));
+    if let Some(stall_sec) = args.watchdog_stall_sec {
+        tokio::spawn(watchdog(
+            state.clone(),
+            metrics.clone(),
+            Duration::from_secs(stall_sec),
+            args.watchdog_exit,
+        ));
+    }
+    tokio::spawn(update_ratio_metrics(
+        state.clone(),
+        metrics.clone(),
+        args.score_weights(),
+    ));
+    tokio::spawn(record_history(
+        state.clone(),
+        Duration::from_secs(args.history_resolution_sec),
+    ));
+    tokio::spawn(count_network_peers(
+        state.clone(),
+        metrics.clone(),
+        config_rx.clone(),
+    ));
+    tokio::spawn(process::run(metrics.clone(), PROCESS_STATS_INTERVAL));
+
+    let http_client = args.http_client()?;
+
+    if let (Some(rpc_url), Some(l1_rpc_url)) = (args.rpc_url.clone(), args.l1_rpc_url.clone()) {
+        let client = contract::connect(rpc_url, l1_rpc_url, args.network).await?;
+        tokio::spawn(contract::run(
+            client,
+            state.clone(),
+            metrics.clone(),
+            Duration::from_secs(args.registry_poll_interval_sec),
+        ));
+    }
+
+    if args.geoip_country_db.is_some() || args.geoip_asn_db.is_some() {
+        let geoip = geoip::GeoIp::open(
+            args.geoip_country_db.as_deref(),
+            args.geoip_asn_db.as_deref(),
+        )?;
+        tokio::spawn(geoip.run(state.clone(), metrics.clone(), GEOIP_UPDATE_INTERVAL));
+    }
+
+    if let Some(endpoint) = args.otlp_endpoint.clone() {
+        tokio::spawn(otlp::run(
+            registry.clone(),
+            endpoint,
+            Duration::from_secs(args.otlp_export_interval_sec),
+        ));
+    }
+
+    if let Some(url) = args.remote_write_url.clone() {
+        tokio::spawn(remote_write::run(
+            http_client.clone(),
+            registry.clone(),
+            url,
+            args.remote_write_bearer_token.clone(),
+            Duration::from_secs(args.remote_write_interval_sec),
+        ));
+    }
+
+    if let Some(path) = args.textfile_path.clone() {
+        tokio::spawn(textfile::run(
+            registry.clone(),
+            path,
+            Duration::from_secs(args.textfile_interval_sec),
+        ));
+    }
+
+    if let Some(url) = args.influx.influx_url.clone() {
+        tokio::spawn(influx::run(
+            http_client.clone(),
+            registry.clone(),
+            args.influx.clone(),
+            url,
+        ));
+    }
+
+    if let Some(addr) = args.graphite_addr.clone() {
+        tokio::spawn(graphite::run(
+            registry.clone(),
+            addr,
+            args.graphite_prefix.clone(),
+            Duration::from_secs(args.graphite_interval_sec),
+        ));
+    }
+
+    if let Some(addr) = &args.statsd_addr {
+        let exporter = statsd::StatsdExporter::connect(addr, registry.clone()).await?;
+        tokio::spawn(exporter.run(Duration::from_secs(args.statsd_interval_sec)));
+    }
+
+    // Sinks flush their buffers once the events channel closes
+    let mut sinks = Vec::new();
+    if let Some(url) = args.clickhouse.clickhouse_url.clone() {
+        let sink =
+            clickhouse::ClickhouseSink::new(http_client.clone(), url, args.clickhouse.clone());
+        sinks.push(tokio::spawn(sink.run(events_tx.subscribe())));
+    }
+
+    #[cfg(feature = "kafka")]
+    if let Some(brokers) = &args.kafka.kafka_brokers {
+        let sink = kafka::KafkaSink::new(brokers, args.kafka.clone())?;
+        sinks.push(tokio::spawn(sink.run(events_tx.subscribe())));
+    }
+
+    if let Some(url) = &args.nats.nats_url {
+        let sink = nats::NatsSink::connect(url, &args.nats).await?;
+        sinks.push(tokio::spawn(sink.run(events_tx.subscribe())));
+    }
+
+    if args.alerts.alert_rules.is_some() {
+        let notifiers = alerts::notifiers(&http_client, &args.alerts);
+        log::info!(
+            "Loaded {} alert rules",
+            config_rx.borrow().alert_rules.len()
+        );
+        let engine = alerts::AlertEngine::new(config_rx.clone(), notifiers, state.clone());
+        sinks.push(tokio::spawn(engine.run(
+            events_tx.subscribe(),
+            Duration::from_secs(args.alerts.alert_evaluation_interval_sec),
+        )));
+    }
+
+    if let Some(path) = &args.record_events {
+        let recorder = recording::EventRecorder::open(path).await?;
+        sinks.push(tokio::spawn(recorder.run(events_tx.subscribe())));
+    }
+
+    if let Some(book) = address_book {
+        sinks.push(tokio::spawn(book.run(
+            events_tx.subscribe(),
+            Duration::from_secs(args.address_book_save_interval_sec),
+        )));
+    }
+
+    let report = args
+        .once
+        .then(|| tokio::spawn(report::EventCounts::default().run(events_tx.subscribe())));
+    let report_started_at = metrics::now();
+
+    let (queue_tx, queue_rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
+    let handler = tokio::spawn(process_events(
+        state.clone(),
+        metrics.clone(),
+        queue_rx,
+        events_tx,
+    ));
+    let simulated = transport.is_none();
+    let transport = match (transport, args.simulation.simulate) {
+        (Some(transport), _) => tokio::spawn(run_transport(
+            transport,
+            state.clone(),
+            queue_tx,
+            config_rx,
+            commands_rx,
+            metrics.clone(),
+            shutdown.clone(),
+        )),
+        (None, workers) => {
+            // Nothing handles transport commands, so senders fail instead of waiting
+            drop(commands_rx);
+            tokio::spawn(simulation::run(
+                args.simulation.clone(),
+                workers.unwrap_or_default(),
+                state.clone(),
+                metrics.clone(),
+                queue_tx,
+                shutdown.clone(),
+            ))
+        }
+    };
+
+    tokio::select! {
+        result = shutdown_signal() => result?,
+        _ = tokio::time::sleep(Duration::from_secs(args.once_duration_sec)), if args.once => {}
+    }
     log::info!("Shutting down");
+    shutdown.cancel();
+    // The event handler and the HTTP server own the remaining event senders.
+    // The handler finishes the queued events once the transport loop stops
+    let _ = transport.await;
+    let _ = handler.await;
+    if let Ok(Err(e)) = http_server.await {
+        log::error!("HTTP server failed: {e:?}");
+    }
+    if tokio::time::timeout(shutdown_timeout, futures::future::join_all(sinks))
+        .await
+        .is_err()
+    {
+        log::warn!("Sinks didn't flush in {shutdown_timeout:?}, some events may be lost");
+    }
+
+    if let Some(report) = report {
+        let report = report::Report::new(
+            &state,
+            report.await?,
+            report_started_at,
+            !simulated,
+            args.score_missing_chunks_threshold,
+        );
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !report.is_healthy() {
+            return Ok(ExitCode::from(2));
+        }
+    }
 
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn shutdown_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+fn network_name(network: sqd_contract_client::Network) -> String {
+    match network {
+        sqd_contract_client::Network::Tethys => "testnet".to_owned(),
+        sqd_contract_client::Network::Mainnet => "mainnet".to_owned(),
+    }
+}
+
+fn new_state(args: &cli::Cli) -> state::State {
+    let uptime = uptime::UptimeTracker::new(
+        Duration::from_secs(args.uptime_max_gap_sec),
+        args.uptime_windows_sec
+            .iter()
+            .copied()
+            .map(Duration::from_secs)
+            .collect(),
+        metrics::now(),
+    );
+    let history =
+        history::History::new((args.history_retention_sec / args.history_resolution_sec) as usize);
+    let success_ratios = success_ratio::SuccessRatioTracker::new(
+        args.success_ratio_windows_sec
+            .iter()
+            .copied()
+            .map(Duration::from_secs)
+            .collect(),
+    );
+    state::State::new(uptime, history, success_ratios)
+}
+
+async fn run_command(command: cli::Command, args: &cli::Cli) -> anyhow::Result<()> {
+    match command {
+        cli::Command::Run => unreachable!("The observer is started by main"),
+        cli::Command::Check => check::run(args).await?,
+        cli::Command::PrintConfig => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&cli::Cli::effective_config()?)?
+            );
+        }
+        cli::Command::GenerateKey { path } => {
+            anyhow::ensure!(!path.exists(), "{} already exists", path.display());
+            let keypair = sqd_network_transport::util::get_keypair(Some(path.clone())).await?;
+            println!("{}", keypair.public().to_peer_id());
+        }
+        cli::Command::PeerId { path } => {
+            anyhow::ensure!(path.is_file(), "{} doesn't exist", path.display());
+            let keypair = sqd_network_transport::util::get_keypair(Some(path)).await?;
+            println!("{}", keypair.public().to_peer_id());
+        }
+        cli::Command::Replay { path } => {
+            let events = recording::load(&path)?;
+            let mut registry =
+                metrics::new_registry(args.metrics_prefix.as_deref(), &args.metrics_labels);
+            let metrics = metrics::Metrics::default();
+            metrics.set_network_name(network_name(args.network));
+            metrics.register(&mut registry);
+            let state = new_state(args);
+            for event in &events {
+                state.record_event();
+                handle_event(&state, &metrics, event);
+            }
+            // Windows end at the last recorded event rather than at the time of the replay
+            let end = events
+                .last()
+                .map_or_else(metrics::now, |event| event.timestamp);
+            update_ratios(&state, &metrics, &args.score_weights(), end);
+            let mut buffer = String::new();
+            prometheus_client::encoding::text::encode(&mut buffer, &registry)?;
+            print!("{buffer}");
+        }
+    }
     Ok(())
 }
 
-async fn run_transport(mut transport: transport::Transport, network_name: String) -> ! {
-    metrics::set_network_name(network_name);
+async fn run_transport(
+    mut transport: transport::Transport,
+    state: Arc<state::State>,
+    queue: mpsc::Sender<(Instant, ObservedEvent)>,
+    mut config_rx: watch::Receiver<cli::RuntimeConfig>,
+    mut commands: mpsc::Receiver<transport::Command>,
+    metrics: Arc<metrics::Metrics>,
+    shutdown: CancellationToken,
+) {
+    let mut probe_interval = config_rx.borrow().probe_interval;
+    let mut health_interval = tokio::time::interval(LOOP_TICK_INTERVAL);
     loop {
-        match transport.select_next_some().await {
-            transport::Event::PeerSeen(event) => {
-                let mut address = event.address;
-                while let Some(libp2p::multiaddr::Protocol::P2p(_)) = address.iter().last() {
-                    address.pop();
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            event = transport.select_next_some() => {
+                metrics.event_loop_event();
+                // Dropping is preferred to blocking, which would stall pings and connection handling
+                if queue.try_send((Instant::now(), event.into())).is_err() {
+                    metrics.event_dropped();
                 }
-                metrics::peer_seen(&event.peer_id.to_string(), &address.to_string());
             }
-            transport::Event::WorkerHeartbeat(event) => {
-                let peer_id = event
-                    .peer_id
-                    .map(|peer_id| peer_id.to_string())
-                    .unwrap_or_else(|| {
-                        log::warn!("Received heartbeat from unknown peer");
-                        "unknown".to_string()
-                    });
-
-                let missing_chunks = event
-                    .heartbeat
-                    .missing_chunks
-                    .map(|bitstring| bitstring.ones())
-                    .unwrap_or_else(|| {
-                        log::warn!("Received heartbeat without missing_chunks from {peer_id}");
-                        0
-                    });
-
-                let assignment_time = chrono::NaiveDateTime::parse_and_remainder(
-                    &event.heartbeat.assignment_id,
-                    "%Y-%m-%dT%H:%M:%S",
-                )
-                .map(|(time, _)| time.and_utc().timestamp())
-                .unwrap_or_else(|e| {
-                    log::warn!(
-                        "Failed to parse assignment_id '{}': {e}",
-                        &event.heartbeat.assignment_id
-                    );
-                    0
+            Ok(()) = config_rx.changed() => {
+                let new_interval = config_rx.borrow_and_update().probe_interval;
+                if new_interval != probe_interval {
+                    log::info!("Changing probe interval to {new_interval:?}");
+                    probe_interval = new_interval;
+                    transport.set_probe_interval(probe_interval);
+                }
+            }
+            Some(command) = commands.recv() => transport.handle_command(command),
+            scheduled = health_interval.tick() => {
+                metrics.event_loop_tick_lag(scheduled.elapsed());
+                metrics.event_queue_depth(queue.max_capacity() - queue.capacity());
+                state.record_loop_tick(transport.connected_boot_nodes());
+                count_connected_peers(&transport, &state, &metrics);
+                metrics.kad_routing_table(&transport.routing_table());
+            }
+        }
+    }
+}
+
+async fn process_events(
+    state: Arc<state::State>,
+    metrics: Arc<metrics::Metrics>,
+    mut queue: mpsc::Receiver<(Instant, ObservedEvent)>,
+    events_tx: broadcast::Sender<ObservedEvent>,
+) {
+    while let Some((received_at, event)) = queue.recv().await {
+        metrics.event_processing_lag(received_at.elapsed());
+        state.record_event();
+        handle_event(&state, &metrics, &event);
+        // Sending only fails when no sinks are subscribed
+        let _ = events_tx.send(event);
+    }
+}
+
+#[cfg(unix)]
+async fn reload_config_on_sighup(
+    config_tx: watch::Sender<cli::RuntimeConfig>,
+) -> anyhow::Result<()> {
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        log::info!("Received SIGHUP, reloading config");
+        match cli::Cli::reload() {
+            Ok(args) => {
+                let config = match args.runtime_config() {
+                    Ok(config) => config,
+                    Err(e) => {
+                        log::error!("Failed to reload config: {e:?}");
+                        continue;
+                    }
+                };
+                logging::set_filter(&config.log_filter);
+                config_tx.send_if_modified(|current| {
+                    let modified = *current != config;
+                    *current = config;
+                    modified
                 });
+            }
+            Err(e) => log::error!("Failed to reload config: {e:?}"),
+        }
+    }
+    Ok(())
+}
 
-                metrics::worker_heartbeat(
-                    &peer_id,
-                    missing_chunks,
-                    event.heartbeat.stored_bytes.unwrap_or_default(),
-                    assignment_time,
-                );
+async fn expire_workers(
+    state: Arc<state::State>,
+    metrics: Arc<metrics::Metrics>,
+    config_rx: watch::Receiver<cli::RuntimeConfig>,
+    transport_commands: mpsc::Sender<transport::Command>,
+) {
+    loop {
+        let ttl = config_rx.borrow().worker_ttl;
+        tokio::time::sleep(EXPIRATION_CHECK_INTERVAL.min(ttl)).await;
+        let mut expired = Vec::new();
+        for peer_id in metrics.expire_peers(ttl) {
+            log::info!("Worker {peer_id} stopped reporting, removing its metrics");
+            state.remove_worker(&peer_id);
+            expired.extend(peer_id.parse().ok());
+        }
+        // The channel is closed when simulating, without a transport to forget the peers
+        if expired.is_empty() {
+            continue;
+        }
+        if let Err(mpsc::error::TrySendError::Full(_)) =
+            transport_commands.try_send(transport::Command::ForgetPeers(expired))
+        {
+            log::warn!("Transport is busy, expired peers will still be probed");
+        }
+    }
+}
+
+async fn detect_silent_workers(
+    state: Arc<state::State>,
+    config_rx: watch::Receiver<cli::RuntimeConfig>,
+) {
+    let mut interval = tokio::time::interval(SILENCE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let threshold = config_rx.borrow().worker_silence_threshold;
+        state.mark_silent(metrics::now() - threshold.as_secs() as i64);
+    }
+}
+
+/// Detects the event stream going quiet while the swarm is connected, which points to a wedged
+/// event loop or handler rather than a quiet network
+async fn watchdog(
+    state: Arc<state::State>,
+    metrics: Arc<metrics::Metrics>,
+    max_silence: Duration,
+    exit: bool,
+) {
+    let started_at = metrics::now();
+    let mut stalled = false;
+    let mut interval = tokio::time::interval(WATCHDOG_CHECK_INTERVAL.min(max_silence));
+    loop {
+        interval.tick().await;
+        let health = state.health();
+        let now = metrics::now();
+        let silence = now - health.last_event.unwrap_or(started_at);
+        if health.connected_boot_nodes == 0 || silence < max_silence.as_secs() as i64 {
+            if stalled {
+                log::info!("Event stream resumed");
+                stalled = false;
             }
-            transport::Event::Ping(event) => {
-                if let Ok(duration) = event.result {
-                    metrics::ping(&event.peer.to_string(), duration);
-                } else {
-                    metrics::ping_failed(&event.peer.to_string());
+            continue;
+        }
+        if stalled {
+            continue;
+        }
+        stalled = true;
+        metrics.event_stream_stall();
+        log::error!(
+            "No events for {silence}s while connected to {} boot nodes, last event loop tick {}",
+            health.connected_boot_nodes,
+            health
+                .last_loop_tick
+                .map_or("never".to_owned(), |t| format!("{}s ago", now - t)),
+        );
+        if exit {
+            // A graceful shutdown might wait on the wedged tasks, so the process exits right away
+            log::error!("Exiting so that the observer gets restarted");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn update_ratio_metrics(
+    state: Arc<state::State>,
+    metrics: Arc<metrics::Metrics>,
+    score_weights: score::ScoreWeights,
+) {
+    let mut interval = tokio::time::interval(RATIO_UPDATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        update_ratios(&state, &metrics, &score_weights, metrics::now());
+    }
+}
+
+/// Exports the ratios and scores of all workers over the windows ending at `now`
+fn update_ratios(
+    state: &state::State,
+    metrics: &metrics::Metrics,
+    score_weights: &score::ScoreWeights,
+    now: i64,
+) {
+    for (peer_id, windows) in state.uptime_ratios(now) {
+        for window in windows {
+            metrics.worker_uptime(&peer_id, &window.window, window.uptime_ratio);
+        }
+    }
+    for (peer_id, windows) in state.success_ratios(now) {
+        for window in windows {
+            metrics.worker_ping_success(&peer_id, &window.window, window.success_ratio);
+        }
+    }
+    for (peer_id, score) in state.update_scores(score_weights, now) {
+        metrics.worker_score(&peer_id, score);
+    }
+}
+
+async fn record_history(state: Arc<state::State>, resolution: Duration) {
+    let mut interval = tokio::time::interval(resolution);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        state.record_history(metrics::now());
+    }
+}
+
+async fn count_network_peers(
+    state: Arc<state::State>,
+    metrics: Arc<metrics::Metrics>,
+    config_rx: watch::Receiver<cli::RuntimeConfig>,
+) {
+    let mut interval = tokio::time::interval(PEER_COUNT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut counts = HashMap::new();
+        for worker in state.workers() {
+            *counts.entry(worker.role_and_version()).or_default() += 1;
+        }
+        metrics.network_peers(counts);
+
+        // Workers that stopped answering pings shouldn't hold back the rollout picture
+        let threshold = config_rx.borrow().worker_silence_threshold;
+        let since = metrics::now() - threshold.as_secs() as i64;
+        metrics.workers_by_version(&state.version_counts(since));
+
+        let window = config_rx.borrow().registered_silence_window;
+        let since = metrics::now() - window.as_secs() as i64;
+        metrics.workers_silent(state.silent_registered_workers(since).len());
+    }
+}
+
+fn count_connected_peers(
+    transport: &transport::Transport,
+    state: &state::State,
+    metrics: &metrics::Metrics,
+) {
+    let mut counts = HashMap::new();
+    for (peer_id, direction) in transport.connected_peers() {
+        let role = if transport.is_boot_node(&peer_id) {
+            "bootnode"
+        } else {
+            state
+                .worker(&peer_id.to_string())
+                .map_or("unknown", |worker| worker.role_class())
+        };
+        *counts.entry((direction, role)).or_default() += 1;
+    }
+    metrics.connected_peers(counts);
+}
+
+fn handle_event(state: &state::State, metrics: &metrics::Metrics, event: &ObservedEvent) {
+    match &event.kind {
+        EventKind::PeerSeen { peer_id, address } => {
+            metrics.peer_seen(peer_id, address, event.timestamp);
+            state.peer_seen(peer_id, address, event.timestamp);
+        }
+        EventKind::PeerIdentified {
+            peer_id,
+            agent_version,
+            protocol_version,
+            protocols,
+            listen_addrs,
+        } => {
+            state.peer_identified(peer_id, agent_version);
+            state.record_identity(state::PeerIdentity {
+                peer_id: peer_id.clone(),
+                agent_version: agent_version.clone(),
+                protocol_version: protocol_version.clone(),
+                protocols: protocols.clone(),
+                listen_addrs: listen_addrs.clone(),
+                identified_at: event.timestamp,
+            });
+        }
+        EventKind::WorkerHeartbeat {
+            peer_id,
+            missing_chunks,
+            stored_bytes,
+            assignment_timestamp,
+        } => {
+            metrics.worker_heartbeat(
+                peer_id,
+                *missing_chunks,
+                *stored_bytes,
+                *assignment_timestamp,
+                event.timestamp,
+            );
+            state.worker_heartbeat(
+                peer_id,
+                *missing_chunks,
+                *stored_bytes,
+                *assignment_timestamp,
+                event.timestamp,
+            );
+        }
+        EventKind::Ping {
+            peer_id, rtt_secs, ..
+        } => {
+            if let Some(rtt_secs) = rtt_secs {
+                // Replayed recordings may have been edited by hand
+                let Ok(duration) = Duration::try_from_secs_f64(*rtt_secs) else {
+                    log::warn!("Ignoring ping from {peer_id} with invalid RTT {rtt_secs}");
+                    return;
+                };
+                metrics.ping(peer_id, duration, event.timestamp);
+                if let Some(stats) = state.ping(peer_id, duration, event.timestamp) {
+                    metrics.ping_rtt_stats(peer_id, &stats);
                 }
+            } else {
+                metrics.ping_failed(peer_id);
+                state.ping_failed(peer_id, event.timestamp);
             }
         }
+        EventKind::DialProbe {
+            peer_id,
+            duration_secs,
+            error,
+        } => {
+            let Ok(duration) = duration_secs.map(Duration::try_from_secs_f64).transpose() else {
+                log::warn!("Ignoring dial probe of {peer_id} with invalid duration");
+                return;
+            };
+            metrics.dial_probe(peer_id, duration, error.as_deref());
+            state.dial_probe(peer_id, duration, event.timestamp);
+        }
     }
 }
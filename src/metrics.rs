@@ -1,111 +1,986 @@
-use std::{sync::{atomic::AtomicU64, Mutex}, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::AtomicU64, Mutex},
+    time::Duration,
+};
 
-use lazy_static::lazy_static;
 use prometheus_client::{
-    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
     registry::Registry,
 };
 
+use crate::{
+    contract::RegisteredWorker,
+    geoip::GeoInfo,
+    process::{ProcessStats, RuntimeStats},
+    rtt::RttStats,
+    state::VersionCount,
+    transport::KadBucket,
+};
+
 type Labels = Vec<(&'static str, String)>;
 
-lazy_static! {
-    pub static ref LAST_SEEN: Family::<Labels, Gauge> = Family::default();
-    pub static ref MISSING_CHUNKS: Family::<Labels, Gauge> = Family::default();
-    pub static ref STORED_BYTES: Family::<Labels, Gauge> = Family::default();
-    pub static ref ASSIGNMENT_TIMESTAMP: Family::<Labels, Gauge> = Family::default();
-    pub static ref PINGS_TOTAL: Family<Labels, Counter> = Family::default();
-    pub static ref LAST_PING_TIME: Family<Labels, Gauge<f64, AtomicU64>> = Family::default();
-    pub static ref NETWORK_NAME: Mutex<String> = Mutex::new("UNDEFINED".to_owned());
+/// Metric families of one observer, registered into the registry it exports
+pub struct Metrics {
+    last_seen: Family<Labels, Gauge>,
+    missing_chunks: Family<Labels, Gauge>,
+    stored_bytes: Family<Labels, Gauge>,
+    assignment_timestamp: Family<Labels, Gauge>,
+    pings_total: Family<Labels, Counter>,
+    last_ping_time: Family<Labels, Gauge<f64, AtomicU64>>,
+    ping_rtt_quantile: Family<Labels, Gauge<f64, AtomicU64>>,
+    ping_rtt_ewma: Family<Labels, Gauge<f64, AtomicU64>>,
+    ping_rtt: Family<Labels, Histogram, fn() -> Histogram>,
+    dial_probes_total: Family<Labels, Counter>,
+    last_dial_success: Family<Labels, Gauge>,
+    last_dial_duration: Family<Labels, Gauge<f64, AtomicU64>>,
+    worker_uptime_ratio: Family<Labels, Gauge<f64, AtomicU64>>,
+    worker_score: Family<Labels, Gauge<f64, AtomicU64>>,
+    worker_ping_success_ratio: Family<Labels, Gauge<f64, AtomicU64>>,
+    network_peers_total: Family<Labels, Gauge>,
+    connected_peers: Family<Labels, Gauge>,
+    kad_routing_table_size: Gauge,
+    kad_bucket_entries: Family<Labels, Gauge>,
+    workers_by_version: Family<Labels, Gauge>,
+    workers_by_country: Family<Labels, Gauge>,
+    workers_by_asn: Family<Labels, Gauge>,
+    stored_bytes_by_country: Family<Labels, Gauge>,
+    stored_bytes_by_asn: Family<Labels, Gauge>,
+    active_registered_workers: Family<Labels, Gauge>,
+    worker_registered: Family<Labels, Gauge>,
+    worker_bond: Family<Labels, Gauge<f64, AtomicU64>>,
+    unregistered_peers: Gauge,
+    workers_silent: Gauge,
+    current_epoch: Gauge,
+    epoch_started_at: Gauge,
+    epoch_remaining: Gauge,
+    status_requests: Family<Labels, Counter>,
+    gossipsub_messages: Family<Labels, Counter>,
+    rate_limited_events: Family<Labels, Counter>,
+    nat_status: Family<Labels, Gauge>,
+    connections_established: Family<Labels, Counter>,
+    hole_punches: Family<Labels, Counter>,
+    connections_denied: Family<Labels, Counter>,
+    workers_expired: Counter,
+    metrics_payload_bytes: Gauge,
+    process_resident_memory: Gauge,
+    process_cpu_seconds: Gauge<f64, AtomicU64>,
+    process_open_fds: Gauge,
+    runtime_workers: Gauge,
+    runtime_alive_tasks: Gauge,
+    runtime_global_queue_depth: Gauge,
+    event_loop_events: Counter,
+    event_loop_tick_lag: Gauge<f64, AtomicU64>,
+    event_queue_depth: Gauge,
+    events_dropped: Counter,
+    event_stream_stalls: Counter,
+    event_processing_lag: Histogram,
+    http_rejected_requests: Family<Labels, Counter>,
+    network_name: Mutex<String>,
+    peer_series: Mutex<HashMap<String, PeerSeries>>,
 }
 
-pub fn set_network_name(network_name: String) {
-    *NETWORK_NAME.lock().unwrap() = network_name;
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            last_seen: Family::default(),
+            missing_chunks: Family::default(),
+            stored_bytes: Family::default(),
+            assignment_timestamp: Family::default(),
+            pings_total: Family::default(),
+            last_ping_time: Family::default(),
+            ping_rtt_quantile: Family::default(),
+            ping_rtt_ewma: Family::default(),
+            ping_rtt: Family::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.001, 2.0, 14))
+            }),
+            dial_probes_total: Family::default(),
+            last_dial_success: Family::default(),
+            last_dial_duration: Family::default(),
+            worker_uptime_ratio: Family::default(),
+            worker_score: Family::default(),
+            worker_ping_success_ratio: Family::default(),
+            network_peers_total: Family::default(),
+            connected_peers: Family::default(),
+            kad_routing_table_size: Gauge::default(),
+            kad_bucket_entries: Family::default(),
+            workers_by_version: Family::default(),
+            workers_by_country: Family::default(),
+            workers_by_asn: Family::default(),
+            stored_bytes_by_country: Family::default(),
+            stored_bytes_by_asn: Family::default(),
+            active_registered_workers: Family::default(),
+            worker_registered: Family::default(),
+            worker_bond: Family::default(),
+            unregistered_peers: Gauge::default(),
+            workers_silent: Gauge::default(),
+            current_epoch: Gauge::default(),
+            epoch_started_at: Gauge::default(),
+            epoch_remaining: Gauge::default(),
+            status_requests: Family::default(),
+            gossipsub_messages: Family::default(),
+            rate_limited_events: Family::default(),
+            nat_status: Family::default(),
+            connections_established: Family::default(),
+            hole_punches: Family::default(),
+            connections_denied: Family::default(),
+            workers_expired: Counter::default(),
+            metrics_payload_bytes: Gauge::default(),
+            process_resident_memory: Gauge::default(),
+            process_cpu_seconds: Gauge::default(),
+            process_open_fds: Gauge::default(),
+            runtime_workers: Gauge::default(),
+            runtime_alive_tasks: Gauge::default(),
+            runtime_global_queue_depth: Gauge::default(),
+            event_loop_events: Counter::default(),
+            event_loop_tick_lag: Gauge::default(),
+            event_queue_depth: Gauge::default(),
+            events_dropped: Counter::default(),
+            event_stream_stalls: Counter::default(),
+            event_processing_lag: Histogram::new(exponential_buckets(0.0001, 4.0, 10)),
+            http_rejected_requests: Family::default(),
+            network_name: Mutex::new("UNDEFINED".to_owned()),
+            peer_series: Default::default(),
+        }
+    }
 }
 
-pub fn peer_seen(peer_id: &str, addr: &str) {
-    LAST_SEEN
-        .get_or_create(&vec![
-            ("peer_id", peer_id.to_owned()),
-            ("addr", addr.to_owned()),
-            ("network", NETWORK_NAME.lock().unwrap().to_owned()),
-        ])
-        .set(now());
+/// Label values created for a peer, needed to remove its series once it expires
+#[derive(Default)]
+struct PeerSeries {
+    last_active: i64,
+    addrs: HashSet<String>,
+    dial_results: HashSet<String>,
+    uptime_windows: HashSet<String>,
+    success_windows: HashSet<String>,
 }
 
-pub fn worker_heartbeat(
-    peer_id: &str,
-    missing_chunks: u64,
-    stored_bytes: u64,
-    assignment_timestamp: i64,
-) {
-    let labels = vec![
-        ("peer_id", peer_id.to_owned()),
-        ("network", NETWORK_NAME.lock().unwrap().to_owned()),
-    ];
-    MISSING_CHUNKS
-        .get_or_create(&labels)
-        .set(missing_chunks as i64);
-    STORED_BYTES.get_or_create(&labels).set(stored_bytes as i64);
-    ASSIGNMENT_TIMESTAMP
-        .get_or_create(&labels)
-        .set(assignment_timestamp);
-}
+impl Metrics {
+    fn track_peer(&self, peer_id: &str, f: impl FnOnce(&mut PeerSeries)) {
+        let mut series = self.peer_series.lock().unwrap();
+        let entry = series
+            .entry(peer_id.to_owned())
+            .or_insert_with(|| PeerSeries {
+                last_active: now(),
+                ..Default::default()
+            });
+        f(entry);
+    }
 
-pub fn ping(peer_id: &str, duration: Duration) {
-    let labels = vec![
-        ("peer_id", peer_id.to_owned()),
-        ("network", NETWORK_NAME.lock().unwrap().to_owned()),
-    ];
-    PINGS_TOTAL.get_or_create(&labels).inc();
-    LAST_PING_TIME
-        .get_or_create(&labels)
-        .set(duration.as_secs_f64());
-}
+    pub fn set_network_name(&self, network_name: String) {
+        *self.network_name.lock().unwrap() = network_name;
+    }
+
+    pub fn peer_seen(&self, peer_id: &str, addr: &str, timestamp: i64) {
+        self.last_seen
+            .get_or_create(&vec![
+                ("peer_id", peer_id.to_owned()),
+                ("addr", addr.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .set(timestamp);
+        self.track_peer(peer_id, |series| {
+            series.addrs.insert(addr.to_owned());
+            series.last_active = timestamp;
+        });
+    }
+
+    pub fn worker_heartbeat(
+        &self,
+        peer_id: &str,
+        missing_chunks: u64,
+        stored_bytes: u64,
+        assignment_timestamp: i64,
+        timestamp: i64,
+    ) {
+        let labels = vec![
+            ("peer_id", peer_id.to_owned()),
+            ("network", self.network_name.lock().unwrap().to_owned()),
+        ];
+        self.missing_chunks
+            .get_or_create(&labels)
+            .set(missing_chunks as i64);
+        self.stored_bytes
+            .get_or_create(&labels)
+            .set(stored_bytes as i64);
+        self.assignment_timestamp
+            .get_or_create(&labels)
+            .set(assignment_timestamp);
+        self.track_peer(peer_id, |series| series.last_active = timestamp);
+    }
+
+    pub fn ping(&self, peer_id: &str, duration: Duration, timestamp: i64) {
+        let labels = vec![
+            ("peer_id", peer_id.to_owned()),
+            ("network", self.network_name.lock().unwrap().to_owned()),
+        ];
+        self.pings_total.get_or_create(&labels).inc();
+        self.last_ping_time
+            .get_or_create(&labels)
+            .set(duration.as_secs_f64());
+        self.ping_rtt
+            .get_or_create(&labels)
+            .observe(duration.as_secs_f64());
+        self.track_peer(peer_id, |series| series.last_active = timestamp);
+    }
+
+    pub fn ping_rtt_stats(&self, peer_id: &str, stats: &RttStats) {
+        let labels = vec![
+            ("peer_id", peer_id.to_owned()),
+            ("network", self.network_name.lock().unwrap().to_owned()),
+        ];
+        self.ping_rtt_ewma
+            .get_or_create(&labels)
+            .set(stats.ewma_secs);
+        for (quantile, value) in [("0.5", stats.p50_secs), ("0.95", stats.p95_secs)] {
+            let mut quantile_labels = labels.clone();
+            quantile_labels.push(("quantile", quantile.to_owned()));
+            self.ping_rtt_quantile
+                .get_or_create(&quantile_labels)
+                .set(value);
+        }
+    }
+
+    pub fn ping_failed(&self, peer_id: &str) {
+        self.last_ping_time.remove(&vec![
+            ("peer_id", peer_id.to_owned()),
+            ("network", self.network_name.lock().unwrap().to_owned()),
+        ]);
+    }
+
+    pub fn dial_probe(&self, peer_id: &str, duration: Option<Duration>, error: Option<&str>) {
+        let labels = vec![
+            ("peer_id", peer_id.to_owned()),
+            ("network", self.network_name.lock().unwrap().to_owned()),
+        ];
+        let result = error.unwrap_or("ok");
+        let mut result_labels = labels.clone();
+        result_labels.push(("result", result.to_owned()));
+        self.dial_probes_total.get_or_create(&result_labels).inc();
+        self.track_peer(peer_id, |series| {
+            series.dial_results.insert(result.to_owned());
+        });
+        match duration {
+            Some(duration) => {
+                self.last_dial_success.get_or_create(&labels).set(1);
+                self.last_dial_duration
+                    .get_or_create(&labels)
+                    .set(duration.as_secs_f64());
+            }
+            None => {
+                self.last_dial_success.get_or_create(&labels).set(0);
+                self.last_dial_duration.remove(&labels);
+            }
+        }
+    }
+
+    pub fn worker_uptime(&self, peer_id: &str, window: &str, ratio: f64) {
+        self.worker_uptime_ratio
+            .get_or_create(&vec![
+                ("peer_id", peer_id.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+                ("window", window.to_owned()),
+            ])
+            .set(ratio);
+        self.track_peer(peer_id, |series| {
+            series.uptime_windows.insert(window.to_owned());
+        });
+    }
+
+    pub fn worker_score(&self, peer_id: &str, score: f64) {
+        self.worker_score
+            .get_or_create(&vec![
+                ("peer_id", peer_id.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .set(score);
+        self.track_peer(peer_id, |_| {});
+    }
+
+    pub fn worker_ping_success(&self, peer_id: &str, window: &str, ratio: f64) {
+        self.worker_ping_success_ratio
+            .get_or_create(&vec![
+                ("peer_id", peer_id.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+                ("window", window.to_owned()),
+            ])
+            .set(ratio);
+        self.track_peer(peer_id, |series| {
+            series.success_windows.insert(window.to_owned());
+        });
+    }
+
+    /// Replaces the per role/version peer counts
+    pub fn network_peers(&self, counts: HashMap<(String, String), i64>) {
+        let network = self.network_name.lock().unwrap().to_owned();
+        self.network_peers_total.clear();
+        for ((role, version), count) in counts {
+            self.network_peers_total
+                .get_or_create(&vec![
+                    ("network", network.clone()),
+                    ("role", role),
+                    ("version", version),
+                ])
+                .set(count);
+        }
+    }
+
+    /// Replaces the counts of peers the observer is currently connected to
+    pub fn connected_peers(&self, counts: HashMap<(&'static str, &'static str), i64>) {
+        let network = self.network_name.lock().unwrap().to_owned();
+        self.connected_peers.clear();
+        for ((direction, role), count) in counts {
+            self.connected_peers
+                .get_or_create(&vec![
+                    ("network", network.clone()),
+                    ("direction", direction.to_owned()),
+                    ("role", role.to_owned()),
+                ])
+                .set(count);
+        }
+    }
+
+    pub fn kad_routing_table(&self, buckets: &[KadBucket]) {
+        let network = self.network_name.lock().unwrap().to_owned();
+        self.kad_routing_table_size
+            .set(buckets.iter().map(|b| b.peers.len() as i64).sum());
+        self.kad_bucket_entries.clear();
+        for bucket in buckets {
+            let index = bucket.index.map_or("none".to_owned(), |i| i.to_string());
+            self.kad_bucket_entries
+                .get_or_create(&vec![("network", network.clone()), ("bucket", index)])
+                .set(bucket.peers.len() as i64);
+        }
+    }
+
+    /// Replaces the counts of recently pinged workers per version
+    pub fn workers_by_version(&self, counts: &[VersionCount]) {
+        let network = self.network_name.lock().unwrap().to_owned();
+        self.workers_by_version.clear();
+        for count in counts {
+            self.workers_by_version
+                .get_or_create(&vec![
+                    ("network", network.clone()),
+                    ("role", count.role.clone()),
+                    ("version", count.version.clone()),
+                ])
+                .set(count.workers as i64);
+        }
+    }
+
+    /// Replaces the series describing the active workers of the on-chain registry.
+    /// Workers that have already left the registry aren't listed by the contract.
+    pub fn registered_workers(&self, workers: &[RegisteredWorker]) {
+        let network = self.network_name.lock().unwrap().to_owned();
+        let deregistering = workers
+            .iter()
+            .filter(|w| w.deregistered_at.is_some())
+            .count();
+        self.active_registered_workers.clear();
+        self.active_registered_workers
+            .get_or_create(&vec![
+                ("network", network.clone()),
+                ("status", "active".to_owned()),
+            ])
+            .set((workers.len() - deregistering) as i64);
+        self.active_registered_workers
+            .get_or_create(&vec![
+                ("network", network.clone()),
+                ("status", "deregistering".to_owned()),
+            ])
+            .set(deregistering as i64);
+
+        self.worker_registered.clear();
+        self.worker_bond.clear();
+        for worker in workers {
+            self.worker_registered
+                .get_or_create(&vec![
+                    ("peer_id", worker.peer_id.clone()),
+                    ("network", network.clone()),
+                    ("onchain_id", worker.onchain_id.clone()),
+                ])
+                .set(1);
+            self.worker_bond
+                .get_or_create(&vec![
+                    ("peer_id", worker.peer_id.clone()),
+                    ("network", network.clone()),
+                ])
+                .set(worker.bond_sqd);
+        }
+    }
+
+    pub fn status_request(&self, result: &str) {
+        self.status_requests
+            .get_or_create(&vec![
+                ("network", self.network_name.lock().unwrap().to_owned()),
+                ("result", result.to_owned()),
+            ])
+            .inc();
+    }
+
+    pub fn gossipsub_message(&self, topic: &str) {
+        self.gossipsub_messages
+            .get_or_create(&vec![
+                ("topic", topic.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .inc();
+    }
+
+    pub fn nat_status(&self, status: &libp2p::autonat::NatStatus) {
+        let current = match status {
+            libp2p::autonat::NatStatus::Public(_) => "public",
+            libp2p::autonat::NatStatus::Private => "private",
+            libp2p::autonat::NatStatus::Unknown => "unknown",
+        };
+        let network = self.network_name.lock().unwrap().to_owned();
+        for status in ["public", "private", "unknown"] {
+            self.nat_status
+                .get_or_create(&vec![
+                    ("status", status.to_owned()),
+                    ("network", network.clone()),
+                ])
+                .set((status == current) as i64);
+        }
+    }
+
+    pub fn connection_established(&self, path: &str) {
+        self.connections_established
+            .get_or_create(&vec![
+                ("path", path.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .inc();
+    }
+
+    pub fn connection_denied(&self, direction: &str) {
+        self.connections_denied
+            .get_or_create(&vec![
+                ("direction", direction.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .inc();
+    }
+
+    pub fn hole_punch(&self, success: bool) {
+        self.hole_punches
+            .get_or_create(&vec![
+                ("result", if success { "ok" } else { "error" }.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .inc();
+    }
+
+    pub fn rate_limited_event(&self, event: &str) {
+        self.rate_limited_events
+            .get_or_create(&vec![
+                ("event", event.to_owned()),
+                ("network", self.network_name.lock().unwrap().to_owned()),
+            ])
+            .inc();
+    }
+
+    pub fn http_request_rejected(&self) {
+        self.http_rejected_requests
+            .get_or_create(&vec![(
+                "network",
+                self.network_name.lock().unwrap().to_owned(),
+            )])
+            .inc();
+    }
+
+    pub fn metrics_payload(&self, bytes: usize) {
+        self.metrics_payload_bytes.set(bytes as i64);
+    }
+
+    pub fn process_stats(&self, process: &ProcessStats, runtime: &RuntimeStats) {
+        if let Some(bytes) = process.resident_memory_bytes {
+            self.process_resident_memory.set(bytes as i64);
+        }
+        if let Some(seconds) = process.cpu_seconds {
+            self.process_cpu_seconds.set(seconds);
+        }
+        if let Some(fds) = process.open_fds {
+            self.process_open_fds.set(fds as i64);
+        }
+        self.runtime_workers.set(runtime.workers as i64);
+        self.runtime_alive_tasks.set(runtime.alive_tasks as i64);
+        self.runtime_global_queue_depth
+            .set(runtime.global_queue_depth as i64);
+    }
+
+    pub fn event_loop_event(&self) {
+        self.event_loop_events.inc();
+    }
+
+    pub fn event_loop_tick_lag(&self, lag: Duration) {
+        self.event_loop_tick_lag.set(lag.as_secs_f64());
+    }
+
+    pub fn event_queue_depth(&self, depth: usize) {
+        self.event_queue_depth.set(depth as i64);
+    }
+
+    pub fn event_dropped(&self) {
+        self.events_dropped.inc();
+    }
+
+    pub fn event_stream_stall(&self) {
+        self.event_stream_stalls.inc();
+    }
+
+    pub fn event_processing_lag(&self, lag: Duration) {
+        self.event_processing_lag.observe(lag.as_secs_f64());
+    }
+
+    pub fn unregistered_peers(&self, count: usize) {
+        self.unregistered_peers.set(count as i64);
+    }
+
+    pub fn workers_silent(&self, count: usize) {
+        self.workers_silent.set(count as i64);
+    }
+
+    pub fn epoch(&self, number: u32, started_at: i64, length: Duration) {
+        self.current_epoch.set(number as i64);
+        self.epoch_started_at.set(started_at);
+        let ends_at = started_at + length.as_secs() as i64;
+        self.epoch_remaining.set((ends_at - now()).max(0));
+    }
+
+    /// Replaces the per-country and per-ASN worker counts and stored bytes
+    pub fn geo_distribution(&self, distribution: HashMap<GeoInfo, (i64, u64)>) {
+        let network = self.network_name.lock().unwrap().to_owned();
+        let mut by_country: HashMap<String, (i64, u64)> = HashMap::new();
+        let mut by_asn: HashMap<(String, String), (i64, u64)> = HashMap::new();
+        for (info, (workers, bytes)) in distribution {
+            let country = by_country.entry(info.country).or_default();
+            country.0 += workers;
+            country.1 += bytes;
+            let asn = by_asn.entry((info.asn, info.as_org)).or_default();
+            asn.0 += workers;
+            asn.1 += bytes;
+        }
+
+        self.workers_by_country.clear();
+        self.stored_bytes_by_country.clear();
+        for (country, (workers, bytes)) in by_country {
+            let labels = vec![("network", network.clone()), ("country", country)];
+            self.workers_by_country.get_or_create(&labels).set(workers);
+            self.stored_bytes_by_country
+                .get_or_create(&labels)
+                .set(bytes as i64);
+        }
+        self.workers_by_asn.clear();
+        self.stored_bytes_by_asn.clear();
+        for ((asn, as_org), (workers, bytes)) in by_asn {
+            let labels = vec![
+                ("network", network.clone()),
+                ("asn", asn),
+                ("as_org", as_org),
+            ];
+            self.workers_by_asn.get_or_create(&labels).set(workers);
+            self.stored_bytes_by_asn
+                .get_or_create(&labels)
+                .set(bytes as i64);
+        }
+    }
+
+    /// Removes all series of the peers that have not been active for `ttl`
+    /// and returns their IDs
+    pub fn expire_peers(&self, ttl: Duration) -> Vec<String> {
+        let deadline = now() - ttl.as_secs() as i64;
+        let network = self.network_name.lock().unwrap().to_owned();
+        let mut series = self.peer_series.lock().unwrap();
+        let expired: Vec<String> = series
+            .iter()
+            .filter(|(_, peer)| peer.last_active < deadline)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        for peer_id in &expired {
+            let peer = series.remove(peer_id).unwrap();
+            self.remove_peer_series(peer_id, &network, &peer);
+            self.workers_expired.inc();
+        }
+        expired
+    }
+
+    /// Whether any series of the peer exist
+    pub fn has_peer(&self, peer_id: &str) -> bool {
+        self.peer_series.lock().unwrap().contains_key(peer_id)
+    }
+
+    /// Removes all series of the peer and returns whether there were any
+    pub fn remove_peer(&self, peer_id: &str) -> bool {
+        let network = self.network_name.lock().unwrap().to_owned();
+        let Some(peer) = self.peer_series.lock().unwrap().remove(peer_id) else {
+            return false;
+        };
+        self.remove_peer_series(peer_id, &network, &peer);
+        true
+    }
+
+    /// Removes the series of all peers, which come back as the peers report again.
+    /// Returns the number of peers whose series were removed.
+    pub fn reset_peers(&self) -> usize {
+        let network = self.network_name.lock().unwrap().to_owned();
+        let series = std::mem::take(&mut *self.peer_series.lock().unwrap());
+        for (peer_id, peer) in &series {
+            self.remove_peer_series(peer_id, &network, peer);
+        }
+        series.len()
+    }
+
+    fn remove_peer_series(&self, peer_id: &str, network: &str, series: &PeerSeries) {
+        let labels = vec![
+            ("peer_id", peer_id.to_owned()),
+            ("network", network.to_owned()),
+        ];
+        self.missing_chunks.remove(&labels);
+        self.stored_bytes.remove(&labels);
+        self.assignment_timestamp.remove(&labels);
+        self.pings_total.remove(&labels);
+        self.last_ping_time.remove(&labels);
+        self.ping_rtt.remove(&labels);
+        self.ping_rtt_ewma.remove(&labels);
+        self.worker_score.remove(&labels);
+        for quantile in ["0.5", "0.95"] {
+            let mut quantile_labels = labels.clone();
+            quantile_labels.push(("quantile", quantile.to_owned()));
+            self.ping_rtt_quantile.remove(&quantile_labels);
+        }
+        self.last_dial_success.remove(&labels);
+        self.last_dial_duration.remove(&labels);
+        for addr in &series.addrs {
+            self.last_seen.remove(&vec![
+                ("peer_id", peer_id.to_owned()),
+                ("addr", addr.clone()),
+                ("network", network.to_owned()),
+            ]);
+        }
+        for result in &series.dial_results {
+            let mut result_labels = labels.clone();
+            result_labels.push(("result", result.clone()));
+            self.dial_probes_total.remove(&result_labels);
+        }
+        for window in &series.uptime_windows {
+            let mut window_labels = labels.clone();
+            window_labels.push(("window", window.clone()));
+            self.worker_uptime_ratio.remove(&window_labels);
+        }
+        for window in &series.success_windows {
+            let mut window_labels = labels.clone();
+            window_labels.push(("window", window.clone()));
+            self.worker_ping_success_ratio.remove(&window_labels);
+        }
+    }
 
-pub fn ping_failed(peer_id: &str) {
-    LAST_PING_TIME.remove(&vec![
-        ("peer_id", peer_id.to_owned()),
-        ("network", NETWORK_NAME.lock().unwrap().to_owned()),
-    ]);
+    pub fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "last_seen",
+            "The timestamp of the last message from the given peer",
+            self.last_seen.clone(),
+        );
+        registry.register(
+            "worker_missing_chunks",
+            "The number of chunks missing from the worker",
+            self.missing_chunks.clone(),
+        );
+        registry.register_with_unit(
+            "worker_storage",
+            "The amount of used storage as reported by the worker",
+            prometheus_client::registry::Unit::Bytes,
+            self.stored_bytes.clone(),
+        );
+        registry.register_with_unit(
+            "worker_assignment_timestamp",
+            "The timestamp of the assignment referenced by the last heartbeat from the worker",
+            prometheus_client::registry::Unit::Seconds,
+            self.assignment_timestamp.clone(),
+        );
+        registry.register(
+            "pings",
+            "The number of pings sent to the worker",
+            self.pings_total.clone(),
+        );
+        registry.register_with_unit(
+            "last_ping",
+            "The duration of the last ping",
+            prometheus_client::registry::Unit::Seconds,
+            self.last_ping_time.clone(),
+        );
+        registry.register_with_unit(
+            "ping_rtt",
+            "The distribution of ping round-trip times",
+            prometheus_client::registry::Unit::Seconds,
+            self.ping_rtt.clone(),
+        );
+        registry.register_with_unit(
+            "ping_rtt_quantile",
+            "Percentiles of recent ping round-trip times, with older pings decaying exponentially",
+            prometheus_client::registry::Unit::Seconds,
+            self.ping_rtt_quantile.clone(),
+        );
+        registry.register_with_unit(
+            "ping_rtt_ewma",
+            "Exponentially weighted moving average of ping round-trip times",
+            prometheus_client::registry::Unit::Seconds,
+            self.ping_rtt_ewma.clone(),
+        );
+        registry.register(
+            "dial_probes",
+            "The number of active dial probes by result",
+            self.dial_probes_total.clone(),
+        );
+        registry.register(
+            "last_dial_success",
+            "Whether the last active dial probe to the peer succeeded",
+            self.last_dial_success.clone(),
+        );
+        registry.register_with_unit(
+            "last_dial_duration",
+            "The time it took to establish the connection in the last dial probe",
+            prometheus_client::registry::Unit::Seconds,
+            self.last_dial_duration.clone(),
+        );
+        registry.register(
+            "worker_uptime_ratio",
+            "The fraction of the window during which the worker answered pings",
+            self.worker_uptime_ratio.clone(),
+        );
+        registry.register(
+            "worker_score",
+            "Weighted reliability score of the worker between 0 and 1",
+            self.worker_score.clone(),
+        );
+        registry.register(
+            "worker_ping_success_ratio",
+            "The fraction of successful pings to the worker within the window",
+            self.worker_ping_success_ratio.clone(),
+        );
+        registry.register(
+            "network_peers_total",
+            "The number of known peers in the network by role and version",
+            self.network_peers_total.clone(),
+        );
+        registry.register(
+            "workers_by_version",
+            "The number of workers that recently answered a ping by version",
+            self.workers_by_version.clone(),
+        );
+        registry.register(
+            "active_registered_workers",
+            "The number of active workers in the on-chain registry by deregistration status",
+            self.active_registered_workers.clone(),
+        );
+        registry.register(
+            "worker_registered",
+            "Set to 1 for every worker in the on-chain registry, to be joined on peer_id",
+            self.worker_registered.clone(),
+        );
+        registry.register(
+            "worker_bond",
+            "The amount of SQD bonded by the worker",
+            self.worker_bond.clone(),
+        );
+        registry.register(
+            "unregistered_peers",
+            "The number of workers answering pings that are not in the on-chain registry",
+            self.unregistered_peers.clone(),
+        );
+        registry.register(
+            "status_requests",
+            "The number of status requests sent to peers by result",
+            self.status_requests.clone(),
+        );
+        registry.register(
+            "gossipsub_messages",
+            "The number of gossipsub messages received by topic",
+            self.gossipsub_messages.clone(),
+        );
+        registry.register_with_unit(
+            "metrics_payload",
+            "The size of the last /metrics response as served, after compression",
+            prometheus_client::registry::Unit::Bytes,
+            self.metrics_payload_bytes.clone(),
+        );
+        registry.register(
+            "http_rejected_requests",
+            "The number of HTTP requests rejected because their source address isn't allowed",
+            self.http_rejected_requests.clone(),
+        );
+        registry.register(
+            "rate_limited_events",
+            "The number of peer messages dropped because their peer exceeded the per-peer budget",
+            self.rate_limited_events.clone(),
+        );
+        registry.register(
+            "nat_status",
+            "Whether the observer's NAT status as detected by AutoNAT is the given one",
+            self.nat_status.clone(),
+        );
+        registry.register(
+            "connected_peers",
+            "The number of peers the observer is connected to by connection direction and peer role",
+            self.connected_peers.clone(),
+        );
+        registry.register(
+            "kad_routing_table_size",
+            "The number of peers in the Kademlia routing table",
+            self.kad_routing_table_size.clone(),
+        );
+        registry.register(
+            "kad_bucket_entries",
+            "The number of peers in each non-empty Kademlia bucket",
+            self.kad_bucket_entries.clone(),
+        );
+        registry.register(
+            "connections_established",
+            "The number of established connections by path type (direct or relayed)",
+            self.connections_established.clone(),
+        );
+        registry.register(
+            "connections_denied",
+            "The number of connections denied because of the configured connection limits",
+            self.connections_denied.clone(),
+        );
+        registry.register(
+            "hole_punches",
+            "The number of DCUtR hole punching attempts by result",
+            self.hole_punches.clone(),
+        );
+        registry.register(
+            "workers_silent",
+            "The number of registered workers that haven't answered a ping recently",
+            self.workers_silent.clone(),
+        );
+        registry.register(
+            "current_epoch",
+            "The number of the current epoch",
+            self.current_epoch.clone(),
+        );
+        registry.register(
+            "epoch_started_at",
+            "Unix timestamp at which the current epoch started",
+            self.epoch_started_at.clone(),
+        );
+        registry.register_with_unit(
+            "epoch_remaining",
+            "Time until the next epoch starts",
+            prometheus_client::registry::Unit::Seconds,
+            self.epoch_remaining.clone(),
+        );
+        registry.register(
+            "workers_by_country",
+            "The number of workers by the country of their public address",
+            self.workers_by_country.clone(),
+        );
+        registry.register(
+            "workers_by_asn",
+            "The number of workers by the autonomous system of their public address",
+            self.workers_by_asn.clone(),
+        );
+        registry.register_with_unit(
+            "stored_by_country",
+            "The amount of data stored by workers by country",
+            prometheus_client::registry::Unit::Bytes,
+            self.stored_bytes_by_country.clone(),
+        );
+        registry.register_with_unit(
+            "stored_by_asn",
+            "The amount of data stored by workers by autonomous system",
+            prometheus_client::registry::Unit::Bytes,
+            self.stored_bytes_by_asn.clone(),
+        );
+        registry.register(
+            "workers_expired",
+            "The number of workers whose series were removed after they stopped reporting",
+            self.workers_expired.clone(),
+        );
+        registry.register_with_unit(
+            "process_resident_memory",
+            "Resident memory of the observer process",
+            prometheus_client::registry::Unit::Bytes,
+            self.process_resident_memory.clone(),
+        );
+        registry.register_with_unit(
+            "process_cpu",
+            "Total user and system CPU time used by the observer process",
+            prometheus_client::registry::Unit::Seconds,
+            self.process_cpu_seconds.clone(),
+        );
+        registry.register(
+            "process_open_fds",
+            "The number of file descriptors open in the observer process",
+            self.process_open_fds.clone(),
+        );
+        registry.register(
+            "runtime_workers",
+            "The number of worker threads of the async runtime",
+            self.runtime_workers.clone(),
+        );
+        registry.register(
+            "runtime_alive_tasks",
+            "The number of tasks alive in the async runtime",
+            self.runtime_alive_tasks.clone(),
+        );
+        registry.register(
+            "runtime_global_queue_depth",
+            "The number of tasks waiting in the runtime's global queue",
+            self.runtime_global_queue_depth.clone(),
+        );
+        registry.register(
+            "event_loop_events",
+            "The number of transport events processed by the event loop",
+            self.event_loop_events.clone(),
+        );
+        registry.register_with_unit(
+            "event_loop_tick_lag",
+            "How late the last periodic tick of the event loop fired, a sign of a busy loop",
+            prometheus_client::registry::Unit::Seconds,
+            self.event_loop_tick_lag.clone(),
+        );
+        registry.register(
+            "event_queue_depth",
+            "The number of received events waiting to be handled",
+            self.event_queue_depth.clone(),
+        );
+        registry.register(
+            "events_dropped",
+            "The number of events dropped because the event queue was full",
+            self.events_dropped.clone(),
+        );
+        registry.register(
+            "event_stream_stalls",
+            "The number of times no events arrived for the watchdog period while boot nodes were connected",
+            self.event_stream_stalls.clone(),
+        );
+        registry.register_with_unit(
+            "event_processing_lag",
+            "Time events spent in the queue before being handled",
+            prometheus_client::registry::Unit::Seconds,
+            self.event_processing_lag.clone(),
+        );
+    }
 }
 
-pub fn register_metrics(registry: &mut Registry) {
-    registry.register(
-        "last_seen",
-        "The timestamp of the last message from the given peer",
-        LAST_SEEN.clone(),
-    );
-    registry.register(
-        "worker_missing_chunks",
-        "The number of chunks missing from the worker",
-        MISSING_CHUNKS.clone(),
-    );
-    registry.register_with_unit(
-        "worker_storage",
-        "The amount of used storage as reported by the worker",
-        prometheus_client::registry::Unit::Bytes,
-        STORED_BYTES.clone(),
-    );
-    registry.register_with_unit(
-        "worker_assignment_timestamp",
-        "The timestamp of the assignment referenced by the last heartbeat from the worker",
-        prometheus_client::registry::Unit::Seconds,
-        ASSIGNMENT_TIMESTAMP.clone(),
-    );
-    registry.register(
-        "pings",
-        "The number of pings sent to the worker",
-        PINGS_TOTAL.clone(),
-    );
-    registry.register_with_unit(
-        "last_ping",
-        "The duration of the last ping",
-        prometheus_client::registry::Unit::Seconds,
-        LAST_PING_TIME.clone(),
-    );
+/// Creates a registry that adds the prefix and constant labels to all metrics registered in it
+pub fn new_registry(prefix: Option<&str>, labels: &[(String, String)]) -> Registry {
+    let labels = labels
+        .iter()
+        .map(|(k, v)| (k.clone().into(), v.clone().into()));
+    match prefix.map(|p| p.trim_end_matches('_')) {
+        Some(prefix) if !prefix.is_empty() => Registry::with_prefix_and_labels(prefix, labels),
+        _ => Registry::with_labels(labels),
+    }
 }
 
-fn now() -> i64 {
+pub fn now() -> i64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
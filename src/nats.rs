@@ -0,0 +1,61 @@
+//! Publishes observed events to NATS subjects persisted in a JetStream stream.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use async_nats::jetstream::{self, context::Context};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{cli::NatsArgs, events::ObservedEvent};
+
+pub struct NatsSink {
+    jetstream: Context,
+    subject_prefix: String,
+}
+
+impl NatsSink {
+    pub async fn connect(url: &str, args: &NatsArgs) -> Result<Self> {
+        let client = async_nats::connect(url).await?;
+        let jetstream = jetstream::new(client);
+        jetstream
+            .get_or_create_stream(jetstream::stream::Config {
+                name: args.nats_stream.clone(),
+                subjects: vec![format!("{}.>", args.nats_subject_prefix)],
+                max_age: Duration::from_secs(args.nats_max_age_sec),
+                ..Default::default()
+            })
+            .await?;
+        log::info!("Publishing events to NATS stream {}", args.nats_stream);
+        Ok(Self {
+            jetstream,
+            subject_prefix: args.nats_subject_prefix.clone(),
+        })
+    }
+
+    pub async fn run(self, mut events: broadcast::Receiver<ObservedEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Err(e) = self.publish(&event).await {
+                        log::warn!("Failed to publish event to NATS: {e:?}");
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("NATS sink is lagging behind, {n} events dropped");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    async fn publish(&self, event: &ObservedEvent) -> Result<()> {
+        let subject = format!("{}.{}", self.subject_prefix, event.kind.type_name());
+        let payload = serde_json::to_vec(event)?;
+        // The second await waits for JetStream to acknowledge that the message is persisted
+        self.jetstream
+            .publish(subject, payload.into())
+            .await?
+            .await?;
+        Ok(())
+    }
+}
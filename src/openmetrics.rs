@@ -0,0 +1,150 @@
+//! Parser for the OpenMetrics text exposition produced by `prometheus_client`,
+//! used by the exporters that push metrics instead of being scraped.
+
+use prometheus_client::{encoding::text::encode, registry::Registry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+    Info,
+    Unknown,
+}
+
+impl MetricType {
+    fn parse(s: &str) -> Self {
+        match s {
+            "counter" => Self::Counter,
+            "gauge" => Self::Gauge,
+            "histogram" => Self::Histogram,
+            "info" => Self::Info,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricFamily {
+    pub name: String,
+    pub metric_type: MetricType,
+    pub help: String,
+    pub unit: String,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// Encodes the registry and parses the result back into metric families
+pub fn collect(registry: &Registry) -> Vec<MetricFamily> {
+    let mut buffer = String::new();
+    encode(&mut buffer, registry).unwrap();
+    parse(&buffer)
+}
+
+pub fn parse(text: &str) -> Vec<MetricFamily> {
+    let mut families: Vec<MetricFamily> = Vec::new();
+    for line in text.lines() {
+        if let Some(descriptor) = line.strip_prefix("# ") {
+            let mut parts = descriptor.splitn(3, ' ');
+            let (Some(kind), Some(name)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let value = parts.next().unwrap_or_default();
+            if families.last().is_none_or(|family| family.name != name) {
+                families.push(MetricFamily {
+                    name: name.to_owned(),
+                    metric_type: MetricType::Unknown,
+                    help: String::new(),
+                    unit: String::new(),
+                    samples: Vec::new(),
+                });
+            }
+            let family = families.last_mut().unwrap();
+            match kind {
+                "TYPE" => family.metric_type = MetricType::parse(value),
+                "HELP" => family.help = unescape(value),
+                "UNIT" => family.unit = value.to_owned(),
+                _ => {}
+            }
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else if let Some(sample) = parse_sample(line) {
+            if let Some(family) = families.last_mut() {
+                family.samples.push(sample);
+            }
+        } else {
+            log::debug!("Couldn't parse metric line: {line}");
+        }
+    }
+    families
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(['{', ' ']).unwrap_or(line.len());
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if let Some(label_str) = rest.strip_prefix('{') {
+        let (parsed, remainder) = parse_labels(label_str)?;
+        labels = parsed;
+        rest = remainder;
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some(Sample {
+        name: name.to_owned(),
+        labels,
+        value,
+    })
+}
+
+/// Parses `name="value",...}` and returns the labels with the remainder of the line
+fn parse_labels(s: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut labels = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start_matches([',', ' ']);
+        if let Some(remainder) = rest.strip_prefix('}') {
+            return Some((labels, remainder));
+        }
+        let (name, after_name) = rest.split_once("=\"")?;
+        let mut value = String::new();
+        let mut chars = after_name.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i,
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        labels.push((name.to_owned(), value));
+        rest = &after_name[end + 1..];
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            ('\\', Some(escaped)) => {
+                result.push(escaped);
+                chars.next();
+            }
+            (c, _) => result.push(c),
+        }
+    }
+    result
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use opentelemetry_proto::tonic::{
+    collector::metrics::v1::{
+        metrics_service_client::MetricsServiceClient, ExportMetricsServiceRequest,
+    },
+    common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue},
+    metrics::v1::{
+        metric::Data, number_data_point, AggregationTemporality, Gauge, Histogram,
+        HistogramDataPoint, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    },
+    resource::v1::Resource,
+};
+use prometheus_client::registry::Registry;
+use tonic::transport::Channel;
+
+use crate::openmetrics::{self, MetricFamily, MetricType};
+
+const SERVICE_NAME: &str = "network-observer";
+
+/// Periodically pushes all registered metric families to an OpenTelemetry collector over OTLP/gRPC
+pub async fn run(registry: Arc<Registry>, endpoint: String, period: Duration) {
+    let start_time = unix_nanos();
+    let mut client: Option<MetricsServiceClient<Channel>> = None;
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        if client.is_none() {
+            match MetricsServiceClient::connect(endpoint.clone()).await {
+                Ok(c) => client = Some(c),
+                Err(e) => {
+                    log::warn!("Failed to connect to OTLP endpoint {endpoint}: {e}");
+                    continue;
+                }
+            }
+        }
+
+        let families = openmetrics::collect(&registry);
+        let request = build_request(&families, start_time, unix_nanos());
+        if let Err(e) = client.as_mut().unwrap().export(request).await {
+            log::warn!("Failed to export metrics over OTLP: {e}");
+            client = None;
+        }
+    }
+}
+
+fn build_request(
+    families: &[MetricFamily],
+    start_time: u64,
+    time: u64,
+) -> ExportMetricsServiceRequest {
+    let metrics = families
+        .iter()
+        .filter(|family| !family.samples.is_empty())
+        .map(|family| convert_family(family, start_time, time))
+        .collect();
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![attribute("service.name", SERVICE_NAME)],
+                ..Default::default()
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: SERVICE_NAME.to_owned(),
+                    version: env!("CARGO_PKG_VERSION").to_owned(),
+                    ..Default::default()
+                }),
+                metrics,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+fn convert_family(family: &MetricFamily, start_time: u64, time: u64) -> Metric {
+    let data = match family.metric_type {
+        MetricType::Counter => Data::Sum(Sum {
+            data_points: number_points(family, start_time, time),
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+            is_monotonic: true,
+        }),
+        MetricType::Histogram => Data::Histogram(Histogram {
+            data_points: histogram_points(family, start_time, time),
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+        }),
+        MetricType::Gauge | MetricType::Info | MetricType::Unknown => Data::Gauge(Gauge {
+            data_points: number_points(family, start_time, time),
+        }),
+    };
+    Metric {
+        name: family.name.clone(),
+        description: family.help.clone(),
+        unit: family.unit.clone(),
+        data: Some(data),
+        ..Default::default()
+    }
+}
+
+fn number_points(family: &MetricFamily, start_time: u64, time: u64) -> Vec<NumberDataPoint> {
+    family
+        .samples
+        .iter()
+        .map(|sample| NumberDataPoint {
+            attributes: attributes(&sample.labels),
+            start_time_unix_nano: start_time,
+            time_unix_nano: time,
+            value: Some(number_data_point::Value::AsDouble(sample.value)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct HistogramSeries {
+    buckets: Vec<(f64, f64)>,
+    sum: f64,
+    count: f64,
+}
+
+fn histogram_points(family: &MetricFamily, start_time: u64, time: u64) -> Vec<HistogramDataPoint> {
+    let mut series: BTreeMap<Vec<(String, String)>, HistogramSeries> = BTreeMap::new();
+    for sample in &family.samples {
+        let suffix = sample
+            .name
+            .strip_prefix(family.name.as_str())
+            .unwrap_or_default();
+        let mut labels = sample.labels.clone();
+        let le = labels
+            .iter()
+            .position(|(name, _)| name == "le")
+            .map(|i| labels.remove(i).1);
+        let entry = series.entry(labels).or_default();
+        match suffix {
+            "_bucket" => {
+                if let Some(le) = le.and_then(|le| le.parse().ok()) {
+                    entry.buckets.push((le, sample.value));
+                }
+            }
+            "_sum" => entry.sum = sample.value,
+            "_count" => entry.count = sample.value,
+            _ => {}
+        }
+    }
+
+    series
+        .into_iter()
+        .map(|(labels, mut hist)| {
+            hist.buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+            // The exposition format has cumulative bucket counts, OTLP expects per-bucket counts
+            let mut previous = 0.0;
+            let bucket_counts = hist
+                .buckets
+                .iter()
+                .map(|(_, cumulative)| {
+                    let count = (cumulative - previous) as u64;
+                    previous = *cumulative;
+                    count
+                })
+                .collect();
+            let explicit_bounds = hist
+                .buckets
+                .iter()
+                .map(|(le, _)| *le)
+                .filter(|le| le.is_finite())
+                .collect();
+            HistogramDataPoint {
+                attributes: attributes(&labels),
+                start_time_unix_nano: start_time,
+                time_unix_nano: time,
+                count: hist.count as u64,
+                sum: Some(hist.sum),
+                bucket_counts,
+                explicit_bounds,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn attributes(labels: &[(String, String)]) -> Vec<KeyValue> {
+    labels
+        .iter()
+        .map(|(name, value)| attribute(name, value))
+        .collect()
+}
+
+fn attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_owned(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_owned())),
+        }),
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
@@ -0,0 +1,72 @@
+//! Resource usage of the observer itself, so that the monitor can be monitored.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::metrics::Metrics;
+
+#[derive(Debug, Default)]
+pub struct ProcessStats {
+    pub resident_memory_bytes: Option<u64>,
+    pub cpu_seconds: Option<f64>,
+    pub open_fds: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct RuntimeStats {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    pub global_queue_depth: usize,
+}
+
+#[cfg(target_os = "linux")]
+pub fn process_stats() -> ProcessStats {
+    let resident_memory_bytes =
+        std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+                let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+                Some(kb * 1024)
+            });
+    let cpu_seconds = std::fs::read_to_string("/proc/self/stat")
+        .ok()
+        .and_then(|stat| {
+            // The command name may contain spaces, so fields are counted from its closing parenthesis
+            let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+            let utime: u64 = fields.next()?.parse().ok()?;
+            let stime: u64 = fields.next()?.parse().ok()?;
+            // SAFETY: sysconf has no preconditions
+            let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+            (ticks_per_sec > 0).then(|| (utime + stime) as f64 / ticks_per_sec as f64)
+        });
+    let open_fds = std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count() as u64);
+    ProcessStats {
+        resident_memory_bytes,
+        cpu_seconds,
+        open_fds,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_stats() -> ProcessStats {
+    ProcessStats::default()
+}
+
+pub fn runtime_stats() -> RuntimeStats {
+    let runtime = tokio::runtime::Handle::current().metrics();
+    RuntimeStats {
+        workers: runtime.num_workers(),
+        alive_tasks: runtime.num_alive_tasks(),
+        global_queue_depth: runtime.global_queue_depth(),
+    }
+}
+
+pub async fn run(metrics: Arc<Metrics>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        metrics.process_stats(&process_stats(), &runtime_stats());
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use libp2p::PeerId;
+
+/// Length of the window over which the per-peer budget applies, in seconds
+const WINDOW_SECS: i64 = 60;
+
+/// Limits the number of messages accepted from each peer per minute
+pub struct RateLimiter {
+    budget: u32,
+    /// Start of the current window and the number of messages received in it
+    peers: HashMap<PeerId, (i64, u32)>,
+    last_pruned: i64,
+}
+
+impl RateLimiter {
+    pub fn new(budget: u32) -> Self {
+        Self {
+            budget,
+            peers: Default::default(),
+            last_pruned: 0,
+        }
+    }
+
+    /// Counts a message from the peer and returns whether it fits into the budget
+    pub fn check(&mut self, peer_id: PeerId, now: i64) -> bool {
+        if now - self.last_pruned >= WINDOW_SECS {
+            // Forget peers that haven't sent anything in the last window
+            self.peers
+                .retain(|_, (window_start, _)| now - *window_start < WINDOW_SECS);
+            self.last_pruned = now;
+        }
+        let (window_start, count) = self.peers.entry(peer_id).or_insert((now, 0));
+        if now - *window_start >= WINDOW_SECS {
+            *window_start = now;
+            *count = 0;
+        }
+        *count += 1;
+        if *count == self.budget + 1 {
+            log::warn!(
+                "Peer {peer_id} exceeded {} messages per minute",
+                self.budget
+            );
+        }
+        *count <= self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_applies_per_peer() {
+        let mut limiter = RateLimiter::new(2);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        assert!(limiter.check(a, 0));
+        assert!(limiter.check(a, 1));
+        assert!(!limiter.check(a, 2));
+        assert!(limiter.check(b, 2));
+    }
+
+    #[test]
+    fn budget_resets_after_window() {
+        let mut limiter = RateLimiter::new(1);
+        let peer = PeerId::random();
+        assert!(limiter.check(peer, 0));
+        assert!(!limiter.check(peer, WINDOW_SECS - 1));
+        assert!(limiter.check(peer, WINDOW_SECS));
+    }
+
+    #[test]
+    fn idle_peers_are_pruned() {
+        let mut limiter = RateLimiter::new(1);
+        limiter.check(PeerId::random(), 0);
+        limiter.check(PeerId::random(), 2 * WINDOW_SECS);
+        assert_eq!(limiter.peers.len(), 1);
+    }
+}
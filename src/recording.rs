@@ -0,0 +1,80 @@
+//! Recording of observed events to a JSON lines file, so that real traffic can be replayed offline.
+
+use std::{
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::broadcast::{self, error::RecvError},
+};
+
+use crate::events::ObservedEvent;
+
+pub struct EventRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl EventRecorder {
+    /// Opens the file for appending, so that restarts don't overwrite earlier recordings
+    pub async fn open(path: &Path) -> Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        log::info!("Recording events to {}", path.display());
+        Ok(Self {
+            path: path.to_owned(),
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub async fn run(mut self, mut events: broadcast::Receiver<ObservedEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Err(e) = self.write(&event).await {
+                        log::warn!("Failed to record event to {}: {e:?}", self.path.display());
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Event recorder is lagging behind, {n} events dropped");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+        if let Err(e) = self.writer.flush().await {
+            log::warn!("Failed to flush {}: {e:?}", self.path.display());
+        }
+    }
+
+    async fn write(&mut self, event: &ObservedEvent) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        Ok(())
+    }
+}
+
+/// Reads a recording made with `--record-events`, skipping empty lines
+pub fn load(path: &Path) -> Result<Vec<ObservedEvent>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut events = Vec::new();
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid event on line {} of {}", i + 1, path.display()))?;
+        events.push(event);
+    }
+    Ok(events)
+}
@@ -0,0 +1,117 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use prometheus_client::registry::Registry;
+use prost::Message;
+
+use crate::openmetrics;
+
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// Periodically pushes all registered series using the Prometheus remote-write protocol
+pub async fn run(
+    client: reqwest::Client,
+    registry: Arc<Registry>,
+    url: String,
+    bearer_token: Option<String>,
+    period: Duration,
+) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let request = build_request(&registry);
+        let count = request.timeseries.len();
+        match push(&client, &url, bearer_token.as_deref(), request).await {
+            Ok(()) => log::debug!("Pushed {count} series to {url}"),
+            Err(e) => log::warn!("Failed to push metrics to {url}: {e:?}"),
+        }
+    }
+}
+
+fn build_request(registry: &Registry) -> WriteRequest {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let timeseries = openmetrics::collect(registry)
+        .into_iter()
+        .flat_map(|family| family.samples)
+        .map(|sample| {
+            let mut labels: Vec<Label> = sample
+                .labels
+                .into_iter()
+                .map(|(name, value)| Label { name, value })
+                .collect();
+            labels.push(Label {
+                name: "__name__".to_owned(),
+                value: sample.name,
+            });
+            // Remote write requires labels to be sorted by name
+            labels.sort_by(|a, b| a.name.cmp(&b.name));
+            TimeSeries {
+                labels,
+                samples: vec![Sample {
+                    value: sample.value,
+                    timestamp,
+                }],
+            }
+        })
+        .collect();
+    WriteRequest { timeseries }
+}
+
+async fn push(
+    client: &reqwest::Client,
+    url: &str,
+    bearer_token: Option<&str>,
+    request: WriteRequest,
+) -> Result<()> {
+    let body = snap::raw::Encoder::new().compress_vec(&request.encode_to_vec())?;
+    let mut builder = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+        .header(reqwest::header::CONTENT_ENCODING, "snappy")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body);
+    if let Some(token) = bearer_token {
+        builder = builder.bearer_auth(token);
+    }
+    let response = builder.send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        anyhow::bail!("Remote write endpoint returned {status}: {text}");
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Summary printed by `--once` after observing the network for a fixed period.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    events::{EventKind, ObservedEvent},
+    metrics::now,
+    state::{State, VersionCount, WorkerInfo},
+};
+
+/// Counts the observed events while the report period lasts
+#[derive(Default)]
+pub struct EventCounts {
+    by_type: BTreeMap<&'static str, u64>,
+    /// Successful and failed pings of every worker
+    pings: HashMap<String, (u64, u64)>,
+    dropped: u64,
+}
+
+impl EventCounts {
+    /// Counts events until the channel closes, then returns the totals
+    pub async fn run(mut self, mut events: broadcast::Receiver<ObservedEvent>) -> Self {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    *self.by_type.entry(event.kind.type_name()).or_default() += 1;
+                    if let EventKind::Ping { peer_id, error, .. } = event.kind {
+                        let (ok, failed) = self.pings.entry(peer_id).or_default();
+                        match error {
+                            None => *ok += 1,
+                            Some(_) => *failed += 1,
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(n)) => self.dropped += n,
+                Err(RecvError::Closed) => break,
+            }
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub events: BTreeMap<&'static str, u64>,
+    /// Events that weren't counted because the report fell behind
+    pub events_not_counted: u64,
+    pub workers_seen: usize,
+    pub workers_pinging: usize,
+    pub versions: Vec<VersionCount>,
+    /// Findings that don't make the network unhealthy by themselves
+    pub anomalies: Vec<String>,
+    /// Reasons why the network is considered unhealthy, which make the process exit with an error
+    pub problems: Vec<String>,
+}
+
+impl Report {
+    pub fn new(
+        state: &State,
+        counts: EventCounts,
+        started_at: i64,
+        expect_boot_nodes: bool,
+        missing_chunks_threshold: u64,
+    ) -> Self {
+        // Other peers found in the DHT or answering pings don't count towards the health verdict
+        let workers: Vec<_> = state
+            .workers()
+            .into_iter()
+            .filter(WorkerInfo::is_worker)
+            .collect();
+        let versions = state.version_counts(started_at);
+        let workers_seen = workers
+            .iter()
+            .filter(|w| w.last_seen.is_some_and(|t| t >= started_at))
+            .count();
+        let workers_pinging = versions.iter().map(|v| v.workers).sum();
+
+        let mut problems = Vec::new();
+        if counts.by_type.is_empty() {
+            problems.push("no events were observed".to_owned());
+        }
+        if expect_boot_nodes && state.health().connected_boot_nodes == 0 {
+            problems.push("no boot node is connected".to_owned());
+        }
+        if workers_pinging == 0 {
+            problems.push("no worker sent a ping".to_owned());
+        }
+
+        let mut anomalies = Vec::new();
+        let always_failing = counts
+            .pings
+            .values()
+            .filter(|&&(ok, failed)| ok == 0 && failed > 0)
+            .count();
+        if always_failing > 0 {
+            anomalies.push(format!("{always_failing} workers failed every ping"));
+        }
+        let missing_chunks = workers
+            .iter()
+            .filter(|w| {
+                w.missing_chunks
+                    .is_some_and(|n| n >= missing_chunks_threshold)
+            })
+            .count();
+        if missing_chunks > 0 {
+            anomalies.push(format!(
+                "{missing_chunks} workers are missing at least {missing_chunks_threshold} chunks"
+            ));
+        }
+        let silent = state.silent_registered_workers(started_at).len();
+        if silent > 0 {
+            anomalies.push(format!("{silent} registered workers didn't ping"));
+        }
+
+        Self {
+            started_at,
+            finished_at: now(),
+            events: counts.by_type,
+            events_not_counted: counts.dropped,
+            workers_seen,
+            workers_pinging,
+            versions,
+            anomalies,
+            problems,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Number of the most recent ping results kept per peer
+const MAX_SAMPLES: usize = 128;
+/// Weight of the newest sample in the moving average
+const EWMA_ALPHA: f64 = 0.2;
+/// Age at which a sample counts half as much as a fresh one in the percentiles
+const HALF_LIFE_SECS: f64 = 600.0;
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct RttStats {
+    pub ewma_secs: f64,
+    pub p50_secs: f64,
+    pub p95_secs: f64,
+    pub samples: usize,
+}
+
+#[derive(Default)]
+struct PeerRtt {
+    ewma: Option<f64>,
+    /// (timestamp, rtt in seconds)
+    samples: VecDeque<(i64, f64)>,
+}
+
+impl PeerRtt {
+    fn record(&mut self, timestamp: i64, rtt: f64) {
+        self.ewma = Some(match self.ewma {
+            Some(ewma) => ewma + EWMA_ALPHA * (rtt - ewma),
+            None => rtt,
+        });
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((timestamp, rtt));
+    }
+
+    fn stats(&self, now: i64) -> Option<RttStats> {
+        let mut weighted: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|&(timestamp, rtt)| {
+                let age = (now - timestamp).max(0) as f64;
+                (rtt, 0.5f64.powf(age / HALF_LIFE_SECS))
+            })
+            .collect();
+        weighted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(RttStats {
+            ewma_secs: self.ewma?,
+            p50_secs: weighted_quantile(&weighted, 0.5),
+            p95_secs: weighted_quantile(&weighted, 0.95),
+            samples: weighted.len(),
+        })
+    }
+}
+
+/// Returns the `q` quantile of samples sorted by value, each given as (value, weight)
+fn weighted_quantile(sorted: &[(f64, f64)], q: f64) -> f64 {
+    let total: f64 = sorted.iter().map(|(_, w)| w).sum();
+    let mut acc = 0.0;
+    for &(value, weight) in sorted {
+        acc += weight;
+        if acc >= q * total {
+            return value;
+        }
+    }
+    sorted.last().map_or(0.0, |(value, _)| *value)
+}
+
+/// Keeps recent ping RTTs of every peer, with older results decaying exponentially
+#[derive(Default)]
+pub struct RttTracker {
+    peers: HashMap<String, PeerRtt>,
+}
+
+impl RttTracker {
+    pub fn record(&mut self, peer_id: &str, timestamp: i64, rtt_secs: f64) -> Option<RttStats> {
+        let peer = self.peers.entry(peer_id.to_owned()).or_default();
+        peer.record(timestamp, rtt_secs);
+        peer.stats(timestamp)
+    }
+
+    pub fn stats(&self, peer_id: &str, now: i64) -> Option<RttStats> {
+        self.peers.get(peer_id)?.stats(now)
+    }
+
+    pub fn remove(&mut self, peer_id: &str) {
+        self.peers.remove(peer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ewma_starts_at_first_sample() {
+        let mut tracker = RttTracker::default();
+        let stats = tracker.record("peer", 0, 0.1).unwrap();
+        assert_eq!(stats.ewma_secs, 0.1);
+        let stats = tracker.record("peer", 1, 0.2).unwrap();
+        assert!((stats.ewma_secs - 0.12).abs() < 1e-9);
+        assert_eq!(stats.samples, 2);
+    }
+
+    #[test]
+    fn quantiles_of_equal_weights() {
+        let sorted = [(1.0, 1.0), (2.0, 1.0), (3.0, 1.0), (4.0, 1.0)];
+        assert_eq!(weighted_quantile(&sorted, 0.5), 2.0);
+        assert_eq!(weighted_quantile(&sorted, 0.95), 4.0);
+        assert_eq!(weighted_quantile(&[], 0.5), 0.0);
+    }
+
+    #[test]
+    fn old_samples_decay() {
+        let mut tracker = RttTracker::default();
+        tracker.record("peer", 0, 1.0);
+        let now = 10 * HALF_LIFE_SECS as i64;
+        tracker.record("peer", now, 0.1);
+        let stats = tracker.stats("peer", now).unwrap();
+        assert_eq!(stats.p50_secs, 0.1);
+        assert_eq!(stats.p95_secs, 0.1);
+    }
+
+    #[test]
+    fn samples_are_capped() {
+        let mut tracker = RttTracker::default();
+        for i in 0..MAX_SAMPLES as i64 + 10 {
+            tracker.record("peer", i, 0.1);
+        }
+        assert_eq!(tracker.stats("peer", 0).unwrap().samples, MAX_SAMPLES);
+        tracker.remove("peer");
+        assert!(tracker.stats("peer", 0).is_none());
+    }
+}
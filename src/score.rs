@@ -0,0 +1,97 @@
+use crate::state::WorkerInfo;
+
+/// Weights of the components of the worker reliability score
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreWeights {
+    pub uptime: f64,
+    pub ping_success: f64,
+    pub missing_chunks: f64,
+    pub reachability: f64,
+    /// Number of missing chunks at which the missing chunks component drops to zero
+    pub missing_chunks_threshold: u64,
+}
+
+/// Inputs of the score that aren't stored in `WorkerInfo`
+pub struct ScoreInputs {
+    pub uptime_ratio: Option<f64>,
+    pub ping_success_ratio: Option<f64>,
+}
+
+impl ScoreWeights {
+    /// Computes the weighted average of all known components, between 0 and 1.
+    /// Components that haven't been measured yet don't count towards the total weight.
+    pub fn score(&self, worker: &WorkerInfo, inputs: &ScoreInputs) -> Option<f64> {
+        let missing_chunks = worker.missing_chunks.map(|missing| {
+            let threshold = self.missing_chunks_threshold.max(1) as f64;
+            1.0 - (missing as f64 / threshold).min(1.0)
+        });
+        let reachability = worker.last_dial_ok.map(|ok| ok as u8 as f64);
+        let components = [
+            (self.uptime, inputs.uptime_ratio),
+            (self.ping_success, inputs.ping_success_ratio),
+            (self.missing_chunks, missing_chunks),
+            (self.reachability, reachability),
+        ];
+        let (total, weights) = components
+            .iter()
+            .filter_map(|&(weight, value)| Some((weight * value?, weight)))
+            .fold((0.0, 0.0), |(total, weights), (value, weight)| {
+                (total + value, weights + weight)
+            });
+        (weights > 0.0).then(|| total / weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHTS: ScoreWeights = ScoreWeights {
+        uptime: 2.0,
+        ping_success: 1.0,
+        missing_chunks: 1.0,
+        reachability: 0.0,
+        missing_chunks_threshold: 100,
+    };
+
+    const NO_INPUTS: ScoreInputs = ScoreInputs {
+        uptime_ratio: None,
+        ping_success_ratio: None,
+    };
+
+    #[test]
+    fn unmeasured_worker_has_no_score() {
+        assert_eq!(WEIGHTS.score(&WorkerInfo::default(), &NO_INPUTS), None);
+    }
+
+    #[test]
+    fn weighted_average_of_known_components() {
+        let inputs = ScoreInputs {
+            uptime_ratio: Some(1.0),
+            ping_success_ratio: Some(0.5),
+        };
+        let worker = WorkerInfo {
+            missing_chunks: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(WEIGHTS.score(&worker, &inputs), Some(3.0 / 4.0));
+    }
+
+    #[test]
+    fn missing_chunks_component_is_clamped() {
+        let worker = WorkerInfo {
+            missing_chunks: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(WEIGHTS.score(&worker, &NO_INPUTS), Some(0.0));
+    }
+
+    #[test]
+    fn zero_weight_components_are_ignored() {
+        let worker = WorkerInfo {
+            last_dial_ok: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(WEIGHTS.score(&worker, &NO_INPUTS), None);
+    }
+}
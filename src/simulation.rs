@@ -0,0 +1,140 @@
+//! Synthetic traffic from fake workers, for load testing and dashboard development
+//! without a live network.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+use rand::{Rng, SeedableRng};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cli::SimulationArgs,
+    events::{EventKind, ObservedEvent},
+    metrics::{now, Metrics},
+    state::State,
+};
+
+const AGENT_VERSIONS: [&str; 3] = ["worker/2.3.1", "worker/2.4.0", "worker/2.4.1"];
+const LOOP_TICK_INTERVAL: Duration = Duration::from_secs(5);
+
+struct FakeWorker {
+    peer_id: String,
+    address: String,
+    agent_version: &'static str,
+    rtt_secs: f64,
+    failure_rate: f64,
+    missing_chunks: u64,
+    stored_bytes: u64,
+}
+
+impl FakeWorker {
+    fn random(index: usize, args: &SimulationArgs, rng: &mut impl Rng) -> Self {
+        let mean_rtt = args.simulate_rtt_ms as f64 / 1000.0;
+        Self {
+            peer_id: PeerId::random().to_string(),
+            address: format!(
+                "/ip4/10.{}.{}.{}/udp/12345/quic-v1",
+                (index >> 16) & 0xff,
+                (index >> 8) & 0xff,
+                index & 0xff
+            ),
+            agent_version: AGENT_VERSIONS[rng.gen_range(0..AGENT_VERSIONS.len())],
+            rtt_secs: rng.gen_range(0.5 * mean_rtt..1.5 * mean_rtt),
+            // Spread failures unevenly so that some workers look flaky
+            failure_rate: rng
+                .gen_range(0.0..=(2.0 * args.simulate_ping_failure_rate).clamp(0.0, 1.0)),
+            missing_chunks: rng.gen_range(0..100),
+            stored_bytes: rng.gen_range(100..1000) << 30,
+        }
+    }
+
+    fn ping(&self, rng: &mut impl Rng) -> EventKind {
+        let failed = rng.gen_bool(self.failure_rate);
+        EventKind::Ping {
+            peer_id: self.peer_id.clone(),
+            rtt_secs: (!failed).then(|| self.rtt_secs * rng.gen_range(0.8..1.2)),
+            error: failed.then(|| "Timeout".to_owned()),
+        }
+    }
+
+    fn heartbeat(&mut self, rng: &mut impl Rng) -> EventKind {
+        self.missing_chunks = self
+            .missing_chunks
+            .saturating_add_signed(rng.gen_range(-10..=10));
+        EventKind::WorkerHeartbeat {
+            peer_id: self.peer_id.clone(),
+            missing_chunks: self.missing_chunks,
+            stored_bytes: self.stored_bytes,
+            assignment_timestamp: now() - now() % 3600,
+        }
+    }
+}
+
+/// Feeds events of fake workers into the event queue in place of the transport loop
+pub async fn run(
+    args: SimulationArgs,
+    workers: usize,
+    state: Arc<State>,
+    metrics: Arc<Metrics>,
+    queue: mpsc::Sender<(Instant, ObservedEvent)>,
+    shutdown: CancellationToken,
+) {
+    log::info!("Simulating {workers} workers");
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let mut workers: Vec<_> = (0..workers)
+        .map(|i| FakeWorker::random(i, &args, &mut rng))
+        .collect();
+    let send = |kind| {
+        metrics.event_loop_event();
+        let event = ObservedEvent {
+            timestamp: now(),
+            kind,
+        };
+        if queue.try_send((Instant::now(), event)).is_err() {
+            metrics.event_dropped();
+        }
+    };
+    for worker in &workers {
+        send(EventKind::PeerSeen {
+            peer_id: worker.peer_id.clone(),
+            address: worker.address.clone(),
+        });
+        send(EventKind::PeerIdentified {
+            peer_id: worker.peer_id.clone(),
+            agent_version: worker.agent_version.to_owned(),
+            protocol_version: "/subsquid/1.0.0".to_owned(),
+            protocols: Vec::new(),
+            listen_addrs: vec![worker.address.clone()],
+        });
+    }
+
+    let mut ping_interval =
+        tokio::time::interval(Duration::from_secs(args.simulate_ping_interval_sec));
+    let mut heartbeat_interval =
+        tokio::time::interval(Duration::from_secs(args.simulate_heartbeat_interval_sec));
+    let mut loop_interval = tokio::time::interval(LOOP_TICK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ping_interval.tick() => {
+                for worker in &workers {
+                    send(worker.ping(&mut rng));
+                }
+            }
+            _ = heartbeat_interval.tick() => {
+                for worker in &mut workers {
+                    send(worker.heartbeat(&mut rng));
+                }
+            }
+            _ = loop_interval.tick() => {
+                // There are no boot nodes, so /readyz keeps reporting the observer as not ready
+                state.record_loop_tick(0);
+                metrics.event_queue_depth(queue.max_capacity() - queue.capacity());
+            }
+        }
+    }
+}
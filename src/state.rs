@@ -0,0 +1,601 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicI64, AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use crate::{
+    contract::RegisteredWorker,
+    history::{History, HistoryMetric, Point},
+    metrics::now,
+    rtt::{RttStats, RttTracker},
+    score::{ScoreInputs, ScoreWeights},
+    success_ratio::{SuccessRatioTracker, WindowRatio},
+    uptime::{UptimeTracker, WindowUptime, WorkerUptime},
+};
+
+const TRANSITIONS_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerStatus {
+    Online,
+    #[default]
+    Silent,
+}
+
+/// Everything the observer currently knows about a single worker
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct WorkerInfo {
+    pub peer_id: String,
+    pub addresses: BTreeSet<String>,
+    pub version: Option<String>,
+    pub status: WorkerStatus,
+    pub last_active: Option<i64>,
+    pub last_seen: Option<i64>,
+    pub last_ping: Option<i64>,
+    pub last_ping_rtt_secs: Option<f64>,
+    pub last_dial: Option<i64>,
+    pub last_dial_ok: Option<bool>,
+    pub last_dial_duration_secs: Option<f64>,
+    pub stored_bytes: Option<u64>,
+    pub missing_chunks: Option<u64>,
+    pub assignment_timestamp: Option<i64>,
+    /// Whether the peer is in the on-chain registry. Unknown if the registry isn't polled.
+    pub registered: Option<bool>,
+    pub onchain_id: Option<String>,
+    /// Weighted reliability score between 0 and 1
+    pub score: Option<f64>,
+}
+
+impl WorkerInfo {
+    /// Splits an agent version like `worker/2.1.0 (abcdef)` into the role and the version.
+    /// Peers that haven't been identified yet are reported as `unknown`.
+    pub fn role_and_version(&self) -> (String, String) {
+        let Some(agent) = &self.version else {
+            return ("unknown".to_owned(), "unknown".to_owned());
+        };
+        let agent = agent.split_whitespace().next().unwrap_or_default();
+        match agent.split_once('/') {
+            Some((role, version)) => (role.to_owned(), version.to_owned()),
+            None => (agent.to_owned(), "unknown".to_owned()),
+        }
+    }
+
+    /// Whether the peer runs a worker, judging by its agent version or by it sending heartbeats
+    pub fn is_worker(&self) -> bool {
+        self.role_class() == "worker" || self.assignment_timestamp.is_some()
+    }
+
+    /// Classifies the peer into one of the known network roles based on its agent version
+    pub fn role_class(&self) -> &'static str {
+        let (role, _) = self.role_and_version();
+        if role.contains("worker") {
+            "worker"
+        } else if role.contains("gateway") || role.contains("portal") {
+            "gateway"
+        } else if role.contains("scheduler") {
+            "scheduler"
+        } else {
+            "unknown"
+        }
+    }
+}
+
+/// A change in the observed state of a worker
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WorkerTransition {
+    CameOnline {
+        peer_id: String,
+        timestamp: i64,
+    },
+    WentSilent {
+        peer_id: String,
+        last_active: Option<i64>,
+    },
+    VersionChanged {
+        peer_id: String,
+        old_version: String,
+        new_version: String,
+    },
+}
+
+impl WorkerTransition {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkerTransition::CameOnline { .. } => "came_online",
+            WorkerTransition::WentSilent { .. } => "went_silent",
+            WorkerTransition::VersionChanged { .. } => "version_changed",
+        }
+    }
+}
+
+/// A registered worker that hasn't answered a ping recently
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SilentWorker {
+    pub peer_id: String,
+    pub onchain_id: String,
+    pub last_ping: Option<i64>,
+    pub last_seen: Option<i64>,
+}
+
+/// The latest identify information received from a peer
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PeerIdentity {
+    pub peer_id: String,
+    pub agent_version: String,
+    pub protocol_version: String,
+    pub protocols: Vec<String>,
+    pub listen_addrs: Vec<String>,
+    pub identified_at: i64,
+}
+
+/// Number of recently pinged workers running a particular version
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VersionCount {
+    pub role: String,
+    pub version: String,
+    pub workers: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HealthSnapshot {
+    pub connected_boot_nodes: usize,
+    pub last_event: Option<i64>,
+    pub last_loop_tick: Option<i64>,
+}
+
+/// Observer state shared between the event loop and the HTTP server
+pub struct State {
+    workers: RwLock<BTreeMap<String, WorkerInfo>>,
+    transitions: broadcast::Sender<WorkerTransition>,
+    last_event: AtomicI64,
+    last_loop_tick: AtomicI64,
+    connected_boot_nodes: AtomicUsize,
+    uptime: Mutex<UptimeTracker>,
+    rtt: Mutex<RttTracker>,
+    success_ratios: Mutex<SuccessRatioTracker>,
+    registered: RwLock<Option<HashMap<String, RegisteredWorker>>>,
+    history: Mutex<History>,
+    identities: RwLock<BTreeMap<String, PeerIdentity>>,
+}
+
+impl State {
+    pub fn new(
+        uptime: UptimeTracker,
+        history: History,
+        success_ratios: SuccessRatioTracker,
+    ) -> Self {
+        Self {
+            workers: Default::default(),
+            transitions: broadcast::channel(TRANSITIONS_CHANNEL_CAPACITY).0,
+            last_event: Default::default(),
+            last_loop_tick: Default::default(),
+            connected_boot_nodes: Default::default(),
+            uptime: Mutex::new(uptime),
+            rtt: Default::default(),
+            success_ratios: Mutex::new(success_ratios),
+            registered: Default::default(),
+            history: Mutex::new(history),
+            identities: Default::default(),
+        }
+    }
+
+    pub fn record_event(&self) {
+        self.last_event.store(now(), Ordering::Relaxed);
+    }
+
+    pub fn record_loop_tick(&self, connected_boot_nodes: usize) {
+        self.last_loop_tick.store(now(), Ordering::Relaxed);
+        self.connected_boot_nodes
+            .store(connected_boot_nodes, Ordering::Relaxed);
+    }
+
+    pub fn health(&self) -> HealthSnapshot {
+        let timestamp = |value: &AtomicI64| Some(value.load(Ordering::Relaxed)).filter(|t| *t > 0);
+        HealthSnapshot {
+            connected_boot_nodes: self.connected_boot_nodes.load(Ordering::Relaxed),
+            last_event: timestamp(&self.last_event),
+            last_loop_tick: timestamp(&self.last_loop_tick),
+        }
+    }
+
+    pub fn peer_seen(&self, peer_id: &str, addr: &str, timestamp: i64) {
+        self.update_worker(peer_id, |worker| {
+            worker.addresses.insert(addr.to_owned());
+            worker.last_seen = Some(timestamp);
+            self.mark_active(worker, timestamp);
+        });
+    }
+
+    pub fn peer_identified(&self, peer_id: &str, agent_version: &str) {
+        self.update_worker(peer_id, |worker| {
+            if let Some(old_version) = &worker.version {
+                if old_version != agent_version {
+                    self.emit(WorkerTransition::VersionChanged {
+                        peer_id: peer_id.to_owned(),
+                        old_version: old_version.clone(),
+                        new_version: agent_version.to_owned(),
+                    });
+                }
+            }
+            worker.version = Some(agent_version.to_owned());
+        });
+    }
+
+    pub fn worker_heartbeat(
+        &self,
+        peer_id: &str,
+        missing_chunks: u64,
+        stored_bytes: u64,
+        assignment_timestamp: i64,
+        timestamp: i64,
+    ) {
+        self.update_worker(peer_id, |worker| {
+            worker.missing_chunks = Some(missing_chunks);
+            worker.stored_bytes = Some(stored_bytes);
+            worker.assignment_timestamp = Some(assignment_timestamp);
+            self.mark_active(worker, timestamp);
+        });
+    }
+
+    /// Records a successful ping and returns the updated RTT statistics of the peer
+    pub fn ping(&self, peer_id: &str, duration: Duration, timestamp: i64) -> Option<RttStats> {
+        self.uptime.lock().unwrap().record(peer_id, timestamp);
+        self.success_ratios
+            .lock()
+            .unwrap()
+            .record(peer_id, true, timestamp);
+        self.update_worker(peer_id, |worker| {
+            worker.last_ping = Some(timestamp);
+            worker.last_ping_rtt_secs = Some(duration.as_secs_f64());
+            self.mark_active(worker, timestamp);
+        });
+        self.rtt
+            .lock()
+            .unwrap()
+            .record(peer_id, timestamp, duration.as_secs_f64())
+    }
+
+    pub fn ping_failed(&self, peer_id: &str, timestamp: i64) {
+        self.success_ratios
+            .lock()
+            .unwrap()
+            .record(peer_id, false, timestamp);
+    }
+
+    pub fn success_ratios(&self, now: i64) -> Vec<(String, Vec<WindowRatio>)> {
+        self.success_ratios.lock().unwrap().all_ratios(now)
+    }
+
+    pub fn rtt_stats(&self, peer_id: &str) -> Option<RttStats> {
+        self.rtt.lock().unwrap().stats(peer_id, now())
+    }
+
+    pub fn dial_probe(&self, peer_id: &str, duration: Option<Duration>, timestamp: i64) {
+        self.update_worker(peer_id, |worker| {
+            worker.last_dial = Some(timestamp);
+            worker.last_dial_ok = Some(duration.is_some());
+            worker.last_dial_duration_secs = duration.map(|d| d.as_secs_f64());
+        });
+    }
+
+    /// Marks online workers without any activity since `deadline` as silent
+    pub fn mark_silent(&self, deadline: i64) {
+        let mut workers = self.workers.write().unwrap();
+        for worker in workers.values_mut() {
+            if worker.status == WorkerStatus::Online
+                && worker.last_active.is_none_or(|t| t < deadline)
+            {
+                worker.status = WorkerStatus::Silent;
+                self.emit(WorkerTransition::WentSilent {
+                    peer_id: worker.peer_id.clone(),
+                    last_active: worker.last_active,
+                });
+            }
+        }
+    }
+
+    pub fn subscribe_transitions(&self) -> broadcast::Receiver<WorkerTransition> {
+        self.transitions.subscribe()
+    }
+
+    pub fn record_identity(&self, identity: PeerIdentity) {
+        self.identities
+            .write()
+            .unwrap()
+            .insert(identity.peer_id.clone(), identity);
+    }
+
+    pub fn identities(&self) -> Vec<PeerIdentity> {
+        self.identities.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn identity(&self, peer_id: &str) -> Option<PeerIdentity> {
+        self.identities.read().unwrap().get(peer_id).cloned()
+    }
+
+    pub fn remove_worker(&self, peer_id: &str) {
+        self.workers.write().unwrap().remove(peer_id);
+        self.identities.write().unwrap().remove(peer_id);
+        self.uptime.lock().unwrap().remove(peer_id);
+        self.rtt.lock().unwrap().remove(peer_id);
+        self.success_ratios.lock().unwrap().remove(peer_id);
+        self.history.lock().unwrap().remove(peer_id);
+    }
+
+    /// Forgets everything observed about all peers, keeping the on-chain registry
+    pub fn remove_all_workers(&self) {
+        let peer_ids: Vec<String> = self.workers.read().unwrap().keys().cloned().collect();
+        for peer_id in peer_ids {
+            self.remove_worker(&peer_id);
+        }
+    }
+
+    pub fn record_history(&self, now: i64) {
+        let workers = self.workers();
+        self.history.lock().unwrap().record(&workers, now);
+    }
+
+    pub fn history(&self, peer_id: &str, metric: HistoryMetric) -> Option<Vec<Point>> {
+        self.history.lock().unwrap().points(peer_id, metric)
+    }
+
+    pub fn worker_uptime(&self, peer_id: &str) -> Option<WorkerUptime> {
+        self.uptime.lock().unwrap().worker_uptime(peer_id, now())
+    }
+
+    /// Recomputes the reliability scores of all workers as of `now` and returns them
+    pub fn update_scores(&self, weights: &ScoreWeights, now: i64) -> Vec<(String, f64)> {
+        let uptime: HashMap<_, _> = self
+            .uptime_ratios(now)
+            .into_iter()
+            .filter_map(|(peer_id, windows)| {
+                let longest = windows.into_iter().max_by_key(|w| w.window_secs)?;
+                Some((peer_id, longest.uptime_ratio))
+            })
+            .collect();
+        let success: HashMap<_, _> = self
+            .success_ratios(now)
+            .into_iter()
+            .filter_map(|(peer_id, windows)| {
+                let longest = windows.into_iter().max_by_key(|w| w.window_secs)?;
+                Some((peer_id, longest.success_ratio))
+            })
+            .collect();
+
+        let mut scores = Vec::new();
+        for worker in self.workers.write().unwrap().values_mut() {
+            let inputs = ScoreInputs {
+                uptime_ratio: uptime.get(&worker.peer_id).copied(),
+                ping_success_ratio: success.get(&worker.peer_id).copied(),
+            };
+            worker.score = weights.score(worker, &inputs);
+            if let Some(score) = worker.score {
+                scores.push((worker.peer_id.clone(), score));
+            }
+        }
+        scores
+    }
+
+    pub fn uptime_ratios(&self, now: i64) -> Vec<(String, Vec<WindowUptime>)> {
+        self.uptime.lock().unwrap().all_ratios(now)
+    }
+
+    /// Counts workers that answered a ping since `since` by their version, most common first.
+    /// Other peers answering pings, like boot nodes and gateways, aren't counted.
+    pub fn version_counts(&self, since: i64) -> Vec<VersionCount> {
+        let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+        for worker in self.workers.read().unwrap().values() {
+            if worker.is_worker() && worker.last_ping.is_some_and(|t| t >= since) {
+                *counts.entry(worker.role_and_version()).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<_> = counts
+            .into_iter()
+            .map(|((role, version), workers)| VersionCount {
+                role,
+                version,
+                workers,
+            })
+            .collect();
+        counts.sort_by(|a, b| b.workers.cmp(&a.workers));
+        counts
+    }
+
+    pub fn set_registered_workers(&self, workers: Vec<RegisteredWorker>) {
+        let workers = workers
+            .into_iter()
+            .map(|worker| (worker.peer_id.clone(), worker))
+            .collect();
+        *self.registered.write().unwrap() = Some(workers);
+    }
+
+    /// Number of workers answering pings that aren't in the on-chain registry
+    pub fn unregistered_pinging_peers(&self) -> usize {
+        let registered = self.registered.read().unwrap();
+        let Some(registered) = registered.as_ref() else {
+            return 0;
+        };
+        self.workers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|w| {
+                w.is_worker() && w.last_ping.is_some() && !registered.contains_key(&w.peer_id)
+            })
+            .count()
+    }
+
+    /// Lists registered workers that haven't answered a ping since `since`
+    pub fn silent_registered_workers(&self, since: i64) -> Vec<SilentWorker> {
+        let registered = self.registered.read().unwrap();
+        let Some(registered) = registered.as_ref() else {
+            return Vec::new();
+        };
+        let workers = self.workers.read().unwrap();
+        let mut silent: Vec<_> = registered
+            .values()
+            .filter_map(|registered| {
+                let observed = workers.get(&registered.peer_id);
+                let last_ping = observed.and_then(|w| w.last_ping);
+                (!last_ping.is_some_and(|t| t >= since)).then(|| SilentWorker {
+                    peer_id: registered.peer_id.clone(),
+                    onchain_id: registered.onchain_id.clone(),
+                    last_ping,
+                    last_seen: observed.and_then(|w| w.last_seen),
+                })
+            })
+            .collect();
+        silent.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        silent
+    }
+
+    pub fn worker(&self, peer_id: &str) -> Option<WorkerInfo> {
+        let worker = self.workers.read().unwrap().get(peer_id)?.clone();
+        Some(self.with_registration(worker))
+    }
+
+    pub fn registered_worker(&self, peer_id: &str) -> Option<RegisteredWorker> {
+        self.registered
+            .read()
+            .unwrap()
+            .as_ref()?
+            .get(peer_id)
+            .cloned()
+    }
+
+    pub fn workers(&self) -> Vec<WorkerInfo> {
+        let workers: Vec<_> = self.workers.read().unwrap().values().cloned().collect();
+        workers
+            .into_iter()
+            .map(|worker| self.with_registration(worker))
+            .collect()
+    }
+
+    fn with_registration(&self, mut worker: WorkerInfo) -> WorkerInfo {
+        if let Some(registered) = self.registered.read().unwrap().as_ref() {
+            let entry = registered.get(&worker.peer_id);
+            worker.registered = Some(entry.is_some());
+            worker.onchain_id = entry.map(|w| w.onchain_id.clone());
+        }
+        worker
+    }
+
+    fn update_worker(&self, peer_id: &str, f: impl FnOnce(&mut WorkerInfo)) {
+        let mut workers = self.workers.write().unwrap();
+        let worker = workers
+            .entry(peer_id.to_owned())
+            .or_insert_with(|| WorkerInfo {
+                peer_id: peer_id.to_owned(),
+                ..Default::default()
+            });
+        f(worker);
+    }
+
+    fn mark_active(&self, worker: &mut WorkerInfo, timestamp: i64) {
+        worker.last_active = Some(timestamp);
+        if worker.status != WorkerStatus::Online {
+            worker.status = WorkerStatus::Online;
+            self.emit(WorkerTransition::CameOnline {
+                peer_id: worker.peer_id.clone(),
+                timestamp,
+            });
+        }
+    }
+
+    fn emit(&self, transition: WorkerTransition) {
+        log::debug!("Worker state transition: {transition:?}");
+        // Sending only fails when nobody is subscribed
+        let _ = self.transitions.send(transition);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(version: Option<&str>) -> WorkerInfo {
+        WorkerInfo {
+            version: version.map(ToOwned::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn agent_version_is_split() {
+        assert_eq!(
+            worker(Some("worker/2.1.0 (abcdef)")).role_and_version(),
+            ("worker".to_owned(), "2.1.0".to_owned())
+        );
+        assert_eq!(
+            worker(Some("gateway")).role_and_version(),
+            ("gateway".to_owned(), "unknown".to_owned())
+        );
+        assert_eq!(
+            worker(None).role_and_version(),
+            ("unknown".to_owned(), "unknown".to_owned())
+        );
+    }
+
+    #[test]
+    fn roles_are_classified() {
+        assert_eq!(worker(Some("sqd-worker/2.1.0")).role_class(), "worker");
+        assert_eq!(worker(Some("portal/0.3.1")).role_class(), "gateway");
+        assert_eq!(worker(Some("scheduler/1.0.0")).role_class(), "scheduler");
+        assert_eq!(worker(Some("rust-libp2p/0.55")).role_class(), "unknown");
+        assert_eq!(worker(None).role_class(), "unknown");
+    }
+
+    fn state_with_pinged_peers() -> State {
+        let state = crate::new_state(&crate::testing::default_args());
+        for (peer_id, agent) in [
+            ("worker", "sqd-worker/2.1.0"),
+            ("gateway", "sqd-gateway/1.0.0"),
+            ("bootnode", "bootnode/1.0.0"),
+        ] {
+            state.peer_identified(peer_id, agent);
+            state.ping(peer_id, Duration::from_millis(50), 100);
+        }
+        state
+    }
+
+    #[test]
+    fn version_counts_only_include_workers() {
+        let counts = state_with_pinged_peers().version_counts(0);
+        assert_eq!(counts.len(), 1);
+        assert_eq!(
+            (counts[0].role.as_str(), counts[0].version.as_str()),
+            ("sqd-worker", "2.1.0")
+        );
+        assert_eq!(counts[0].workers, 1);
+    }
+
+    #[test]
+    fn only_workers_count_as_unregistered() {
+        let state = state_with_pinged_peers();
+        assert_eq!(state.unregistered_pinging_peers(), 0);
+        state.set_registered_workers(Vec::new());
+        assert_eq!(state.unregistered_pinging_peers(), 1);
+    }
+
+    #[test]
+    fn workers_are_recognized_by_heartbeats() {
+        assert!(worker(Some("sqd-worker/2.1.0")).is_worker());
+        assert!(!worker(Some("bootnode/1.0.0")).is_worker());
+        assert!(!worker(None).is_worker());
+        let unidentified = WorkerInfo {
+            assignment_timestamp: Some(1_700_000_000),
+            ..Default::default()
+        };
+        assert!(unidentified.is_worker());
+    }
+}
@@ -0,0 +1,103 @@
+//! Mirrors counters and gauges to a StatsD server, with labels sent as DogStatsD tags.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use prometheus_client::registry::Registry;
+use tokio::net::UdpSocket;
+
+use crate::openmetrics::{self, MetricType};
+
+/// Keeps datagrams below the typical MTU so that they aren't fragmented
+const MAX_PACKET_SIZE: usize = 1432;
+
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    registry: Arc<Registry>,
+    /// Last exported value of each counter series, as StatsD counters are increments
+    counters: HashMap<String, f64>,
+}
+
+impl StatsdExporter {
+    pub async fn connect(addr: &str, registry: Arc<Registry>) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(addr).await?;
+        log::info!("Sending metrics to StatsD at {addr}");
+        Ok(Self {
+            socket,
+            registry,
+            counters: Default::default(),
+        })
+    }
+
+    pub async fn run(mut self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            for packet in self.packets() {
+                if let Err(e) = self.socket.send(packet.as_bytes()).await {
+                    log::warn!("Failed to send metrics to StatsD: {e:?}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Formats all counters and gauges and groups the lines into datagrams
+    fn packets(&mut self) -> Vec<String> {
+        let mut packets = Vec::new();
+        let mut packet = String::new();
+        for family in openmetrics::collect(&self.registry) {
+            let kind = match family.metric_type {
+                MetricType::Counter => "c",
+                MetricType::Gauge => "g",
+                _ => continue,
+            };
+            for sample in family.samples {
+                let tags: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}:{}", sanitize_tag(v)))
+                    .collect();
+                let series = if tags.is_empty() {
+                    sample.name
+                } else {
+                    format!("{}|#{}", sample.name, tags.join(","))
+                };
+                let value = if kind == "c" {
+                    let previous = self.counters.insert(series.clone(), sample.value);
+                    // A counter that went down was reset, so all of its value is new
+                    match previous {
+                        Some(previous) if previous <= sample.value => sample.value - previous,
+                        _ => sample.value,
+                    }
+                } else {
+                    sample.value
+                };
+                if kind == "c" && value == 0.0 {
+                    continue;
+                }
+                let line = match series.split_once('|') {
+                    Some((name, tags)) => format!("{name}:{value}|{kind}|{tags}"),
+                    None => format!("{series}:{value}|{kind}"),
+                };
+                if !packet.is_empty() && packet.len() + line.len() + 1 > MAX_PACKET_SIZE {
+                    packets.push(std::mem::take(&mut packet));
+                }
+                if !packet.is_empty() {
+                    packet.push('\n');
+                }
+                packet.push_str(&line);
+            }
+        }
+        if !packet.is_empty() {
+            packets.push(packet);
+        }
+        packets
+    }
+}
+
+/// Replaces the characters that separate fields and tags in the DogStatsD format
+fn sanitize_tag(value: &str) -> String {
+    value.replace([',', '|', '#', '\n'], "_")
+}
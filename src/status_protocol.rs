@@ -0,0 +1,95 @@
+//! Request-response protocol used to ask workers for their current status.
+
+use std::{io, marker::PhantomData};
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{request_response, StreamProtocol};
+use prost::Message;
+
+/// Status requests don't carry any data
+#[derive(Clone, PartialEq, Message)]
+pub struct StatusRequest {}
+
+const MAX_RESPONSE_SIZE: u64 = 1 << 20;
+
+/// Encodes each request and response as a single protobuf message that spans the whole stream
+pub struct ProstCodec<Req, Resp> {
+    _marker: PhantomData<fn() -> (Req, Resp)>,
+}
+
+impl<Req, Resp> Default for ProstCodec<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for ProstCodec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+async fn read_message<M: Message + Default, T: AsyncRead + Unpin + Send>(
+    io: &mut T,
+) -> io::Result<M> {
+    let mut buf = Vec::new();
+    io.take(MAX_RESPONSE_SIZE).read_to_end(&mut buf).await?;
+    M::decode(buf.as_slice()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<M: Message, T: AsyncWrite + Unpin + Send>(
+    io: &mut T,
+    message: M,
+) -> io::Result<()> {
+    io.write_all(&message.encode_to_vec()).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl<Req, Resp> request_response::Codec for ProstCodec<Req, Resp>
+where
+    Req: Message + Default + Send + 'static,
+    Resp: Message + Default + Send + 'static,
+{
+    type Protocol = StreamProtocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Req) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, req).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        resp: Resp,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, resp).await
+    }
+}
+
+pub type StatusCodec = ProstCodec<StatusRequest, sqd_messages::Heartbeat>;
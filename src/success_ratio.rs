@@ -0,0 +1,144 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use crate::uptime::window_label;
+
+/// Ping results are aggregated into buckets of this length to bound memory use
+const BUCKET_SECS: i64 = 60;
+
+#[derive(Debug, Clone)]
+pub struct WindowRatio {
+    pub window: String,
+    pub window_secs: u64,
+    pub success_ratio: f64,
+}
+
+/// Number of successful and total pings in one bucket
+#[derive(Default)]
+struct Bucket {
+    start: i64,
+    ok: u32,
+    total: u32,
+}
+
+/// Tracks ping success ratios of workers over sliding windows
+pub struct SuccessRatioTracker {
+    windows: Vec<Duration>,
+    max_window: i64,
+    workers: HashMap<String, VecDeque<Bucket>>,
+}
+
+impl SuccessRatioTracker {
+    pub fn new(windows: Vec<Duration>) -> Self {
+        let max_window = windows
+            .iter()
+            .map(|w| w.as_secs() as i64)
+            .max()
+            .unwrap_or(0);
+        Self {
+            windows,
+            max_window,
+            workers: Default::default(),
+        }
+    }
+
+    pub fn record(&mut self, peer_id: &str, ok: bool, now: i64) {
+        let buckets = self.workers.entry(peer_id.to_owned()).or_default();
+        let start = now - now.rem_euclid(BUCKET_SECS);
+        if buckets.back().is_none_or(|b| b.start != start) {
+            buckets.push_back(Bucket {
+                start,
+                ..Default::default()
+            });
+        }
+        let bucket = buckets.back_mut().unwrap();
+        bucket.total += 1;
+        bucket.ok += ok as u32;
+        while buckets
+            .front()
+            .is_some_and(|b| b.start + BUCKET_SECS <= now - self.max_window)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    pub fn remove(&mut self, peer_id: &str) {
+        self.workers.remove(peer_id);
+    }
+
+    /// Returns the ratios of all workers for the windows in which they were pinged at least once
+    pub fn all_ratios(&self, now: i64) -> Vec<(String, Vec<WindowRatio>)> {
+        self.workers
+            .iter()
+            .map(|(peer_id, buckets)| {
+                let ratios = self
+                    .windows
+                    .iter()
+                    .filter_map(|window| {
+                        let since = now - window.as_secs() as i64;
+                        let (ok, total) = buckets
+                            .iter()
+                            .filter(|b| b.start + BUCKET_SECS > since)
+                            .fold((0, 0), |(ok, total), b| (ok + b.ok, total + b.total));
+                        (total > 0).then(|| WindowRatio {
+                            window: window_label(*window),
+                            window_secs: window.as_secs(),
+                            success_ratio: ok as f64 / total as f64,
+                        })
+                    })
+                    .collect();
+                (peer_id.clone(), ratios)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratios(tracker: &SuccessRatioTracker, now: i64) -> Vec<(String, f64)> {
+        let mut all = tracker.all_ratios(now);
+        assert_eq!(all.len(), 1);
+        all.pop()
+            .unwrap()
+            .1
+            .into_iter()
+            .map(|ratio| (ratio.window, ratio.success_ratio))
+            .collect()
+    }
+
+    #[test]
+    fn ratio_per_window() {
+        let mut tracker =
+            SuccessRatioTracker::new(vec![Duration::from_secs(300), Duration::from_secs(3600)]);
+        // Failures over half an hour ago, successes in the last minutes
+        tracker.record("peer", false, 1000);
+        tracker.record("peer", false, 1010);
+        tracker.record("peer", true, 3000);
+        tracker.record("peer", true, 3050);
+        assert_eq!(
+            ratios(&tracker, 3100),
+            [("5m".to_owned(), 1.0), ("1h".to_owned(), 0.5)]
+        );
+    }
+
+    #[test]
+    fn windows_without_pings_are_skipped() {
+        let mut tracker =
+            SuccessRatioTracker::new(vec![Duration::from_secs(60), Duration::from_secs(3600)]);
+        tracker.record("peer", true, 0);
+        assert_eq!(ratios(&tracker, 600), [("1h".to_owned(), 1.0)]);
+    }
+
+    #[test]
+    fn buckets_older_than_longest_window_are_dropped() {
+        let mut tracker = SuccessRatioTracker::new(vec![Duration::from_secs(600)]);
+        tracker.record("peer", false, 0);
+        tracker.record("peer", true, 1200);
+        assert_eq!(tracker.workers["peer"].len(), 1);
+        assert_eq!(ratios(&tracker, 1200), [("10m".to_owned(), 1.0)]);
+    }
+}
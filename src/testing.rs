@@ -0,0 +1,207 @@
+//! In-process harness that runs events through the observer's event handling without a network.
+
+use std::{pin::pin, sync::Arc, time::Instant};
+
+use clap::{CommandFactory, FromArgMatches};
+use futures::{Stream, StreamExt};
+use prometheus_client::{encoding::text::encode, registry::Registry};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    cli::Cli,
+    events::ObservedEvent,
+    metrics::{self, Metrics},
+    state::State,
+};
+
+pub struct TestObserver {
+    pub state: Arc<State>,
+    /// Events as they were published to the sinks
+    pub published: broadcast::Receiver<ObservedEvent>,
+    registry: Registry,
+}
+
+impl TestObserver {
+    /// Handles all events of the stream with a fresh state and registry, like the live observer would
+    pub async fn run(events: impl Stream<Item = ObservedEvent>) -> Self {
+        let args = default_args();
+        let mut registry = metrics::new_registry(None, &[]);
+        let metrics = Arc::new(Metrics::default());
+        metrics.set_network_name("test".to_owned());
+        metrics.register(&mut registry);
+        let state = Arc::new(crate::new_state(&args));
+        let (events_tx, published) = broadcast::channel(crate::EVENTS_CHANNEL_CAPACITY);
+        let (queue_tx, queue_rx) = mpsc::channel(crate::EVENT_QUEUE_CAPACITY);
+        let handler = tokio::spawn(crate::process_events(
+            state.clone(),
+            metrics,
+            queue_rx,
+            events_tx,
+        ));
+
+        let mut events = pin!(events);
+        while let Some(event) = events.next().await {
+            queue_tx
+                .send((Instant::now(), event))
+                .await
+                .expect("Handler should be running");
+        }
+        drop(queue_tx);
+        handler.await.expect("Handler shouldn't panic");
+
+        Self {
+            state,
+            published,
+            registry,
+        }
+    }
+
+    pub fn metrics_text(&self) -> String {
+        let mut buffer = String::new();
+        encode(&mut buffer, &self.registry).unwrap();
+        buffer
+    }
+
+    /// Returns the value of the series with the given name that has all the given labels
+    pub fn metric(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.metrics_text().lines().find_map(|line| {
+            if line.starts_with('#') {
+                return None;
+            }
+            let (series, value) = line.rsplit_once(' ')?;
+            let (series_name, series_labels) = series.split_once('{').unwrap_or((series, ""));
+            if series_name != name {
+                return None;
+            }
+            let series_labels = parse_labels(series_labels.strip_suffix('}').unwrap_or(""))?;
+            labels
+                .iter()
+                .all(|label| series_labels.contains(label))
+                .then(|| value.parse().ok())
+                .flatten()
+        })
+    }
+}
+
+/// Arguments with every option at its default, ignoring the environment of the test process
+pub fn default_args() -> Cli {
+    let matches = Cli::command()
+        .mut_args(|arg| arg.env(None))
+        .try_get_matches_from(["observer", "--ephemeral-identity"])
+        .expect("Default arguments should be valid");
+    Cli::from_arg_matches(&matches).expect("Default arguments should be valid")
+}
+
+/// Splits the `key="value",...` label set of an encoded series
+fn parse_labels(mut labels: &str) -> Option<Vec<(&str, &str)>> {
+    let mut result = Vec::new();
+    while !labels.is_empty() {
+        let (key, rest) = labels.split_once("=\"")?;
+        let (value, rest) = rest.split_once('"')?;
+        result.push((key, value));
+        labels = rest.strip_prefix(',').unwrap_or(rest);
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+    use crate::events::EventKind;
+
+    const PEER_ID: &str = "12D3KooWBwbQFT48cNYGPbDwm8rjasbZkc1VMo6rCR6217qr165S";
+
+    fn event(kind: EventKind) -> ObservedEvent {
+        ObservedEvent {
+            timestamp: metrics::now(),
+            kind,
+        }
+    }
+
+    fn ping(rtt_secs: Option<f64>) -> ObservedEvent {
+        event(EventKind::Ping {
+            peer_id: PEER_ID.to_owned(),
+            rtt_secs,
+            error: rtt_secs.is_none().then(|| "Timeout".to_owned()),
+        })
+    }
+
+    #[tokio::test]
+    async fn successful_ping_updates_worker_and_metrics() {
+        let observer = TestObserver::run(stream::iter([ping(Some(0.05)), ping(Some(0.07))])).await;
+
+        let labels = [("peer_id", PEER_ID), ("network", "test")];
+        assert_eq!(observer.metric("pings_total", &labels), Some(2.0));
+        assert_eq!(observer.metric("last_ping_seconds", &labels), Some(0.07));
+        let worker = observer.state.worker(PEER_ID).unwrap();
+        assert_eq!(worker.last_ping_rtt_secs, Some(0.07));
+    }
+
+    #[tokio::test]
+    async fn failed_ping_removes_last_ping_duration() {
+        let observer = TestObserver::run(stream::iter([ping(Some(0.05)), ping(None)])).await;
+
+        let labels = [("peer_id", PEER_ID)];
+        assert_eq!(observer.metric("pings_total", &labels), Some(1.0));
+        assert_eq!(observer.metric("last_ping_seconds", &labels), None);
+    }
+
+    #[tokio::test]
+    async fn invalid_rtt_is_ignored() {
+        let observer = TestObserver::run(stream::iter([ping(Some(-1.0)), ping(Some(1e300))])).await;
+
+        assert_eq!(
+            observer.metric("pings_total", &[("peer_id", PEER_ID)]),
+            None
+        );
+        assert!(observer.state.worker(PEER_ID).is_none());
+    }
+
+    #[tokio::test]
+    async fn heartbeat_sets_worker_gauges() {
+        let heartbeat = event(EventKind::WorkerHeartbeat {
+            peer_id: PEER_ID.to_owned(),
+            missing_chunks: 12,
+            stored_bytes: 1 << 30,
+            assignment_timestamp: 1_700_000_000,
+        });
+        let observer = TestObserver::run(stream::iter([heartbeat])).await;
+
+        let labels = [("peer_id", PEER_ID)];
+        assert_eq!(
+            observer.metric("worker_missing_chunks", &labels),
+            Some(12.0)
+        );
+        assert_eq!(
+            observer.metric("worker_storage_bytes", &labels),
+            Some((1u64 << 30) as f64)
+        );
+        let worker = observer.state.worker(PEER_ID).unwrap();
+        assert_eq!(worker.missing_chunks, Some(12));
+    }
+
+    #[tokio::test]
+    async fn handled_events_are_published_in_order() {
+        let seen = event(EventKind::PeerSeen {
+            peer_id: PEER_ID.to_owned(),
+            address: "/ip4/10.0.0.1/udp/12345/quic-v1".to_owned(),
+        });
+        let mut observer = TestObserver::run(stream::iter([seen, ping(Some(0.05))])).await;
+
+        let first = observer.published.try_recv().unwrap();
+        assert_eq!(first.kind.type_name(), "peer_seen");
+        let second = observer.published.try_recv().unwrap();
+        assert_eq!(second.kind.type_name(), "ping");
+        assert!(observer.published.try_recv().is_err());
+    }
+
+    #[test]
+    fn labels_are_compared_exactly() {
+        let labels = parse_labels(r#"peer_id="ab",network="test""#).unwrap();
+        assert_eq!(labels, [("peer_id", "ab"), ("network", "test")]);
+        assert!(!labels.contains(&("peer_id", "a")));
+        assert_eq!(parse_labels(""), Some(vec![]));
+    }
+}
@@ -0,0 +1,75 @@
+//! Writes all metrics to a file in the Prometheus text format, for node_exporter's textfile collector.
+
+use std::{fmt::Write, path::PathBuf, sync::Arc, time::Duration};
+
+use prometheus_client::registry::Registry;
+
+use crate::openmetrics::{self, MetricFamily, MetricType};
+
+/// Periodically replaces the file with the current values of all registered series
+pub async fn run(registry: Arc<Registry>, path: PathBuf, period: Duration) {
+    // node_exporter only reads `*.prom` files, so the temporary file is never picked up half-written
+    let tmp_path = path.with_extension("tmp");
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let text = render(&openmetrics::collect(&registry));
+        let result =
+            std::fs::write(&tmp_path, text).and_then(|()| std::fs::rename(&tmp_path, &path));
+        if let Err(e) = result {
+            log::warn!("Failed to write metrics to {}: {e:?}", path.display());
+        }
+    }
+}
+
+/// Renders the families in the Prometheus text format 0.0.4
+fn render(families: &[MetricFamily]) -> String {
+    let mut text = String::new();
+    for family in families {
+        if family.samples.is_empty() {
+            continue;
+        }
+        let metric_type = match family.metric_type {
+            MetricType::Counter => "counter",
+            MetricType::Gauge | MetricType::Info => "gauge",
+            MetricType::Histogram => "histogram",
+            MetricType::Unknown => "untyped",
+        };
+        // OpenMetrics names counter and info families without the suffix their samples have
+        let name = match family.metric_type {
+            MetricType::Counter | MetricType::Info => family.samples[0].name.as_str(),
+            _ => family.name.as_str(),
+        };
+        let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+        writeln!(text, "# HELP {name} {help}").unwrap();
+        writeln!(text, "# TYPE {name} {metric_type}").unwrap();
+        for sample in &family.samples {
+            text.push_str(&sample.name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                    .collect();
+                write!(text, "{{{}}}", labels.join(",")).unwrap();
+            }
+            writeln!(text, " {}", format_value(sample.value)).unwrap();
+        }
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    match value {
+        v if v == f64::INFINITY => "+Inf".to_owned(),
+        v if v == f64::NEG_INFINITY => "-Inf".to_owned(),
+        v => v.to_string(),
+    }
+}
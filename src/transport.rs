@@ -1,13 +1,31 @@
-use std::{collections::VecDeque, task::Poll, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    task::Poll,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use futures::StreamExt;
 use libp2p::{
+    allow_block_list::{self, AllowedPeers, BlockedPeers},
+    connection_limits::{ConnectionLimits, Exceeded},
+    gossipsub::{self, IdentTopic, TopicHash},
     identity::Keypair,
     metrics::{Metrics as Libp2pMetrics, Recorder},
-    swarm::SwarmEvent,
-    Multiaddr, PeerId, SwarmBuilder,
+    multiaddr::Protocol,
+    request_response::{self, OutboundRequestId},
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId, DialError, ListenError, SwarmEvent,
+    },
+    Multiaddr, PeerId, StreamProtocol, SwarmBuilder,
 };
+use prometheus_client::registry::Registry;
+use prost::Message;
+use serde::Serialize;
+use tokio::sync::oneshot;
 
 use sqd_contract_client::Network;
 use sqd_network_transport::{
@@ -17,21 +35,97 @@ use sqd_network_transport::{
     AgentInfo,
 };
 
-use crate::cli::Cli;
+use crate::{
+    cli::Cli,
+    metrics::{self, Metrics},
+    rate_limit::RateLimiter,
+    status_protocol::{StatusCodec, StatusRequest},
+};
+
+const GOSSIPSUB_MAX_MESSAGE_SIZE: usize = 1 << 20;
 
 pub struct Transport {
     swarm: libp2p::Swarm<Behaviour>,
     events: VecDeque<Event>,
     libp2p_metrics: Libp2pMetrics,
+    metrics: Arc<Metrics>,
+    known_peers: HashSet<PeerId>,
+    probe_interval: Option<tokio::time::Interval>,
+    pending_probes: HashMap<ConnectionId, PeerId>,
+    boot_nodes: Vec<PeerId>,
+    crawl_interval: tokio::time::Interval,
+    heartbeat_topic: TopicHash,
+    status_replies: HashMap<OutboundRequestId, (StatusReply, Instant)>,
+    connections: HashMap<ConnectionId, (PeerId, &'static str)>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+/// Receives the status of a worker requested on demand, or the reason the request failed
+pub type StatusReply = oneshot::Sender<Result<StatusResponse, String>>;
+
+/// Requests from other tasks that need access to the swarm
+pub enum Command {
+    RequestStatus(PeerId, StatusReply),
+    DumpRoutingTable(oneshot::Sender<Vec<KadBucket>>),
+    /// Blocks (`true`) or unblocks (`false`) a peer and replies with all blocked peers
+    SetBlocked(PeerId, bool, oneshot::Sender<Vec<PeerId>>),
+    BlockedPeers(oneshot::Sender<Vec<PeerId>>),
+    /// Stops probing peers whose series expired
+    ForgetPeers(Vec<PeerId>),
+}
+
+/// Non-empty bucket of the Kademlia routing table
+#[derive(Debug, Clone, Serialize)]
+pub struct KadBucket {
+    /// Base-2 logarithm of the distance covered by the bucket
+    pub index: Option<u32>,
+    pub peers: Vec<KadPeer>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KadPeer {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+}
+
+pub struct StatusResponse {
+    pub heartbeat: sqd_messages::Heartbeat,
+    pub duration: Duration,
 }
 
 pub enum Event {
-    // Gossipsub(GossipsubMessage),
     PeerSeen(PeerSeen),
-    // TODO: reuse base behavior to poll statuses from workers
-    #[allow(dead_code)]
+    PeerIdentified(PeerIdentified),
     WorkerHeartbeat(WorkerHeartbeat),
     Ping(libp2p::ping::Event),
+    DialProbe(DialProbe),
+}
+
+impl Event {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Event::PeerSeen(_) => "peer_seen",
+            Event::PeerIdentified(_) => "peer_identified",
+            Event::WorkerHeartbeat(_) => "worker_heartbeat",
+            Event::Ping(_) => "ping",
+            Event::DialProbe(_) => "dial_probe",
+        }
+    }
+
+    /// Whether the event is a message sent by the peer rather than a result of the observer's activity
+    fn is_inbound(&self) -> bool {
+        matches!(self, Event::PeerIdentified(_) | Event::WorkerHeartbeat(_))
+    }
+
+    fn peer_id(&self) -> Option<PeerId> {
+        match self {
+            Event::PeerSeen(e) => Some(e.peer_id),
+            Event::PeerIdentified(e) => Some(e.peer_id),
+            Event::WorkerHeartbeat(e) => e.peer_id,
+            Event::Ping(e) => Some(e.peer),
+            Event::DialProbe(e) => Some(e.peer_id),
+        }
+    }
 }
 
 pub struct PeerSeen {
@@ -39,55 +133,242 @@ pub struct PeerSeen {
     pub address: Multiaddr,
 }
 
+pub struct PeerIdentified {
+    pub peer_id: PeerId,
+    pub agent_version: String,
+    pub protocol_version: String,
+    pub protocols: Vec<StreamProtocol>,
+    pub listen_addrs: Vec<Multiaddr>,
+}
+
+pub struct DialProbe {
+    pub peer_id: PeerId,
+    pub result: Result<Duration, &'static str>,
+}
+
 pub struct WorkerHeartbeat {
     pub peer_id: Option<PeerId>,
     pub heartbeat: sqd_messages::Heartbeat,
 }
 
 impl Transport {
-    pub async fn build(args: Cli, libp2p_metrics: Libp2pMetrics) -> Result<Self> {
-        let keypair = get_keypair(Some(args.key.clone())).await?;
+    pub async fn build(
+        args: &Cli,
+        registry: &mut Registry,
+        metrics: Arc<Metrics>,
+        known_addresses: Vec<(PeerId, Vec<Multiaddr>)>,
+    ) -> Result<Self> {
+        if args.ephemeral_identity {
+            log::info!("Using an ephemeral identity");
+        }
+        // Without a key file a new random key is generated
+        let keypair = get_keypair(args.key.clone()).await?;
+        log::info!("Local peer ID: {}", keypair.public().to_peer_id());
 
+        let status_protocol = StreamProtocol::try_from_owned(args.status_protocol.clone())?;
+        let status_timeout = Duration::from_secs(args.status_request_timeout_sec);
+        let limits = ConnectionLimits::default()
+            .with_max_established(args.max_connections)
+            .with_max_established_per_peer(args.max_connections_per_peer)
+            .with_max_pending_incoming(args.max_pending_connections)
+            .with_max_pending_outgoing(args.max_pending_connections);
+        let allowed_peers = (!args.allow_peers.is_empty()).then(|| {
+            args.allow_peers
+                .iter()
+                .chain(args.boot_nodes.iter().map(|node| &node.peer_id))
+                .copied()
+                .collect::<HashSet<_>>()
+        });
         let mut swarm = SwarmBuilder::with_existing_identity(keypair)
             .with_tokio()
             .with_quic()
             .with_dns()?
-            .with_behaviour(|key| Behaviour::new(key, args.network))?
+            .with_relay_client(libp2p::noise::Config::new, libp2p::yamux::Config::default)?
+            .with_bandwidth_metrics(registry)
+            .with_behaviour(|key, relay_client| {
+                Behaviour::new(
+                    key,
+                    relay_client,
+                    limits.clone(),
+                    allowed_peers.clone(),
+                    args.network,
+                    status_protocol.clone(),
+                    status_timeout,
+                )
+            })?
             .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(120)))
             .build();
 
-        for addr in args.p2p_listen_addrs {
-            swarm.listen_on(addr)?;
+        let heartbeat_topic = IdentTopic::new(&args.heartbeat_topic);
+        let mut topics = vec![heartbeat_topic.clone()];
+        topics.extend(args.gossipsub_topics.iter().map(IdentTopic::new));
+        for topic in topics {
+            log::info!("Subscribing to gossipsub topic {topic}");
+            swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+        }
+
+        for addr in &args.p2p_listen_addrs {
+            swarm.listen_on(addr.clone())?;
         }
-        for public_addr in args.p2p_public_addrs {
+        for public_addr in &args.p2p_public_addrs {
             log::info!("Adding public address {public_addr}");
-            swarm.add_external_address(public_addr);
+            swarm.add_external_address(public_addr.clone());
         }
 
-        for node in args.boot_nodes {
+        for peer_id in &args.block_peers {
+            log::info!("Blocking peer {peer_id}");
+            swarm.behaviour_mut().blocked.block_peer(*peer_id);
+        }
+
+        let mut boot_nodes = Vec::new();
+        for node in &args.boot_nodes {
             log::info!("Adding bootnode {node:?}");
+            boot_nodes.push(node.peer_id);
             swarm
                 .behaviour_mut()
                 .kademlia
-                .add_address(&node.peer_id, node.address);
+                .add_address(&node.peer_id, node.address.clone());
             swarm.dial(node.peer_id)?;
         }
 
-        Ok(Self {
+        let mut known_peers = HashSet::new();
+        if !known_addresses.is_empty() {
+            log::info!(
+                "Restoring {} peers from the address book",
+                known_addresses.len()
+            );
+        }
+        for (peer_id, addrs) in known_addresses {
+            known_peers.insert(peer_id);
+            for addr in addrs {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+            }
+        }
+
+        let crawl_interval = delayed_interval(Duration::from_secs(args.crawl_interval_sec));
+
+        let mut transport = Self {
             swarm,
             events: Default::default(),
-            libp2p_metrics,
-        })
+            libp2p_metrics: Libp2pMetrics::new(registry),
+            metrics,
+            known_peers,
+            probe_interval: None,
+            pending_probes: Default::default(),
+            boot_nodes,
+            crawl_interval,
+            heartbeat_topic: heartbeat_topic.hash(),
+            status_replies: Default::default(),
+            connections: Default::default(),
+            rate_limiter: args.max_messages_per_peer_per_minute.map(RateLimiter::new),
+        };
+        transport.set_probe_interval(args.probe_interval_sec.map(Duration::from_secs));
+        Ok(transport)
+    }
+
+    pub fn is_boot_node(&self, peer_id: &PeerId) -> bool {
+        self.boot_nodes.contains(peer_id)
+    }
+
+    /// Returns the currently connected peers with the direction of each connection (`inbound` or `outbound`)
+    pub fn connected_peers(&self) -> HashSet<(PeerId, &'static str)> {
+        self.connections.values().copied().collect()
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    pub fn connected_boot_nodes(&self) -> usize {
+        self.boot_nodes
+            .iter()
+            .filter(|peer_id| self.swarm.is_connected(peer_id))
+            .count()
+    }
+
+    pub fn set_probe_interval(&mut self, period: Option<Duration>) {
+        self.probe_interval = period.map(delayed_interval);
     }
 
     pub fn poll_event(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Event> {
         while self.events.is_empty() {
+            if let Some(interval) = &mut self.probe_interval {
+                if interval.poll_tick(cx).is_ready() {
+                    self.probe_peers();
+                    continue;
+                }
+            }
+            if self.crawl_interval.poll_tick(cx).is_ready() {
+                self.crawl();
+                continue;
+            }
             match futures::ready!(self.swarm.poll_next_unpin(cx)).unwrap() {
                 SwarmEvent::NewListenAddr { address, .. } => log::info!("Listening on {address:?}"),
+                SwarmEvent::ConnectionEstablished {
+                    peer_id,
+                    connection_id,
+                    endpoint,
+                    established_in,
+                    ..
+                } => {
+                    let relayed = endpoint
+                        .get_remote_address()
+                        .iter()
+                        .any(|p| p == Protocol::P2pCircuit);
+                    self.metrics
+                        .connection_established(if relayed { "relayed" } else { "direct" });
+                    let direction = if endpoint.is_dialer() {
+                        "outbound"
+                    } else {
+                        "inbound"
+                    };
+                    self.connections.insert(connection_id, (peer_id, direction));
+                    if self.pending_probes.remove(&connection_id).is_some() {
+                        self.swarm.close_connection(connection_id);
+                        self.events.push_back(Event::DialProbe(DialProbe {
+                            peer_id,
+                            result: Ok(established_in),
+                        }));
+                    }
+                }
+                SwarmEvent::ConnectionClosed { connection_id, .. } => {
+                    self.connections.remove(&connection_id);
+                }
+                SwarmEvent::IncomingConnectionError {
+                    error: ListenError::Denied { cause },
+                    ..
+                } if cause.downcast_ref::<Exceeded>().is_some() => {
+                    self.metrics.connection_denied("incoming");
+                }
+                SwarmEvent::OutgoingConnectionError {
+                    connection_id,
+                    error,
+                    ..
+                } => {
+                    if let DialError::Denied { cause } = &error {
+                        if cause.downcast_ref::<Exceeded>().is_some() {
+                            self.metrics.connection_denied("outgoing");
+                        }
+                    }
+                    if let Some(peer_id) = self.pending_probes.remove(&connection_id) {
+                        log::debug!("Probe dial to {peer_id} failed: {error:?}");
+                        self.events.push_back(Event::DialProbe(DialProbe {
+                            peer_id,
+                            result: Err(dial_error_reason(&error)),
+                        }));
+                    }
+                }
                 SwarmEvent::Behaviour(event) => match event {
                     BehaviourEvent::Ping(e) => self.on_ping(e),
                     BehaviourEvent::Identify(e) => self.on_identify(e),
                     BehaviourEvent::Kademlia(e) => self.on_kademlia(e),
+                    BehaviourEvent::Gossipsub(e) => self.on_gossipsub(e),
+                    BehaviourEvent::Status(e) => self.on_status(e),
+                    BehaviourEvent::Autonat(e) => self.on_autonat(e),
+                    BehaviourEvent::RelayClient(e) => log::debug!("Relay client event: {e:?}"),
+                    BehaviourEvent::Dcutr(e) => self.on_dcutr(e),
+                    BehaviourEvent::Limits(e) | BehaviourEvent::Blocked(e) => match e {},
+                    BehaviourEvent::Allowed(e) => match e {},
                 },
                 _ => {}
             };
@@ -95,16 +376,248 @@ impl Transport {
         Poll::Ready(self.events.pop_front().unwrap())
     }
 
+    /// Returns the next event that doesn't come from a blocked or not allowed peer.
+    /// Messages sent by the peer also have to fit into its budget.
+    fn poll_allowed_event(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Event> {
+        loop {
+            let event = futures::ready!(self.poll_event(cx));
+            let Some(peer_id) = event.peer_id() else {
+                return Poll::Ready(event);
+            };
+            if !self.is_allowed(&peer_id) {
+                continue;
+            }
+            if let Some(limiter) = self.rate_limiter.as_mut().filter(|_| event.is_inbound()) {
+                if !limiter.check(peer_id, metrics::now()) {
+                    self.metrics.rate_limited_event(event.type_name());
+                    continue;
+                }
+            }
+            return Poll::Ready(event);
+        }
+    }
+
+    fn probe_peers(&mut self) {
+        log::debug!("Probing {} known peers", self.known_peers.len());
+        let peers: Vec<_> = self
+            .known_peers
+            .iter()
+            .copied()
+            .filter(|peer_id| self.is_allowed(peer_id))
+            .collect();
+        for peer_id in peers {
+            let opts = DialOpts::peer_id(peer_id)
+                .condition(PeerCondition::Always)
+                .build();
+            let connection_id = opts.connection_id();
+            match self.swarm.dial(opts) {
+                Ok(()) => {
+                    self.pending_probes.insert(connection_id, peer_id);
+                }
+                Err(e) => self.events.push_back(Event::DialProbe(DialProbe {
+                    peer_id,
+                    result: Err(dial_error_reason(&e)),
+                })),
+            }
+        }
+    }
+
+    pub fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::RequestStatus(peer_id, reply) => self.request_status(peer_id, reply),
+            Command::DumpRoutingTable(reply) => {
+                let _ = reply.send(self.routing_table());
+            }
+            Command::SetBlocked(peer_id, blocked, reply) => {
+                // Blocking also closes all existing connections to the peer
+                if blocked {
+                    log::info!("Blocking peer {peer_id}");
+                    self.swarm.behaviour_mut().blocked.block_peer(peer_id);
+                } else {
+                    log::info!("Unblocking peer {peer_id}");
+                    self.swarm.behaviour_mut().blocked.unblock_peer(peer_id);
+                }
+                let _ = reply.send(self.blocked_peers());
+            }
+            Command::BlockedPeers(reply) => {
+                let _ = reply.send(self.blocked_peers());
+            }
+            Command::ForgetPeers(peers) => {
+                for peer_id in peers {
+                    self.known_peers.remove(&peer_id);
+                }
+            }
+        }
+    }
+
+    /// Remembers a discovered peer for probing. Peers whose events are
+    /// dropped never expire, so they aren't remembered in the first place.
+    fn add_known_peer(&mut self, peer_id: PeerId) {
+        if self.is_allowed(&peer_id) {
+            self.known_peers.insert(peer_id);
+        }
+    }
+
+    /// The lists of the allow and block list behaviours are the only source of truth,
+    /// so that events are filtered exactly like connections
+    fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        let behaviour = self.swarm.behaviour();
+        !behaviour.blocked.blocked_peers().contains(peer_id)
+            && behaviour
+                .allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.allowed_peers().contains(peer_id))
+    }
+
+    fn blocked_peers(&self) -> Vec<PeerId> {
+        self.swarm
+            .behaviour()
+            .blocked
+            .blocked_peers()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    pub fn routing_table(&mut self) -> Vec<KadBucket> {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .kbuckets()
+            .map(|bucket| KadBucket {
+                index: bucket.range().0.ilog2(),
+                peers: bucket
+                    .iter()
+                    .map(|entry| KadPeer {
+                        peer_id: entry.node.key.preimage().to_string(),
+                        addresses: entry.node.value.iter().map(|a| a.to_string()).collect(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Requests the status of a single peer and sends the result to `reply`
+    fn request_status(&mut self, peer_id: PeerId, reply: StatusReply) {
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .status
+            .send_request(&peer_id, StatusRequest {});
+        self.status_replies
+            .insert(request_id, (reply, Instant::now()));
+    }
+
+    fn on_status(
+        &mut self,
+        event: request_response::Event<StatusRequest, sqd_messages::Heartbeat>,
+    ) {
+        log::trace!("Status event: {event:?}");
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => {
+                self.metrics.status_request("ok");
+                if let Some((reply, started)) = self.status_replies.remove(&request_id) {
+                    // The requester may have given up waiting
+                    let _ = reply.send(Ok(StatusResponse {
+                        heartbeat: response.clone(),
+                        duration: started.elapsed(),
+                    }));
+                }
+                self.events
+                    .push_back(Event::WorkerHeartbeat(WorkerHeartbeat {
+                        peer_id: Some(peer),
+                        heartbeat: response,
+                    }));
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                log::debug!("Status request to {peer} failed: {error}");
+                self.metrics.status_request(status_error_reason(&error));
+                if let Some((reply, _)) = self.status_replies.remove(&request_id) {
+                    let _ = reply.send(Err(error.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Looks up the peers closest to a random key. Repeated random walks eventually
+    /// discover the whole network, not only the peers that happen to contact us.
+    fn crawl(&mut self) {
+        let key = PeerId::random();
+        log::debug!("Starting DHT random walk towards {key}");
+        self.swarm.behaviour_mut().kademlia.get_closest_peers(key);
+    }
+
     fn on_ping(&mut self, event: libp2p::ping::Event) {
         log::trace!("Ping event: {event:?}");
         self.libp2p_metrics.record(&event);
         self.events.push_back(Event::Ping(event));
     }
 
-    #[allow(clippy::needless_pass_by_value)]
     fn on_identify(&mut self, event: libp2p::identify::Event) {
         log::debug!("Identify event: {event:?}");
         self.libp2p_metrics.record(&event);
+        if let libp2p::identify::Event::Received { peer_id, info, .. } = event {
+            for address in info.listen_addrs.iter().cloned() {
+                self.events
+                    .push_back(Event::PeerSeen(PeerSeen { peer_id, address }));
+            }
+            self.events.push_back(Event::PeerIdentified(PeerIdentified {
+                peer_id,
+                agent_version: info.agent_version,
+                protocol_version: info.protocol_version,
+                protocols: info.protocols,
+                listen_addrs: info.listen_addrs,
+            }));
+        }
+    }
+
+    fn on_autonat(&mut self, event: libp2p::autonat::Event) {
+        log::trace!("AutoNAT event: {event:?}");
+        if let libp2p::autonat::Event::StatusChanged { old, new } = event {
+            log::info!("NAT status changed from {old:?} to {new:?}");
+            self.metrics.nat_status(&new);
+        }
+    }
+
+    fn on_dcutr(&mut self, event: libp2p::dcutr::Event) {
+        log::debug!("DCUtR event: {event:?}");
+        self.libp2p_metrics.record(&event);
+        self.metrics.hole_punch(event.result.is_ok());
+    }
+
+    fn on_gossipsub(&mut self, event: gossipsub::Event) {
+        log::trace!("Gossipsub event: {event:?}");
+        self.libp2p_metrics.record(&event);
+        let gossipsub::Event::Message { message, .. } = event else {
+            return;
+        };
+        self.metrics.gossipsub_message(message.topic.as_str());
+        if message.topic != self.heartbeat_topic {
+            return;
+        }
+        match sqd_messages::Heartbeat::decode(message.data.as_slice()) {
+            Ok(heartbeat) => self
+                .events
+                .push_back(Event::WorkerHeartbeat(WorkerHeartbeat {
+                    peer_id: message.source,
+                    heartbeat,
+                })),
+            Err(e) => log::debug!("Invalid heartbeat from {:?}: {e}", message.source),
+        }
     }
 
     fn on_kademlia(&mut self, event: libp2p::kad::Event) {
@@ -114,6 +627,7 @@ impl Transport {
             libp2p::kad::Event::RoutingUpdated {
                 peer, addresses, ..
             } => {
+                self.add_known_peer(peer);
                 for address in addresses.into_vec() {
                     self.events.push_back(Event::PeerSeen(PeerSeen {
                         peer_id: peer,
@@ -121,7 +635,27 @@ impl Transport {
                     }))
                 }
             }
+            libp2p::kad::Event::OutboundQueryProgressed {
+                result: libp2p::kad::QueryResult::GetClosestPeers(result),
+                ..
+            } => {
+                let peers = match result {
+                    Ok(ok) => ok.peers,
+                    Err(libp2p::kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                };
+                log::debug!("DHT random walk found {} peers", peers.len());
+                for peer in peers {
+                    self.add_known_peer(peer.peer_id);
+                    for address in peer.addrs {
+                        self.events.push_back(Event::PeerSeen(PeerSeen {
+                            peer_id: peer.peer_id,
+                            address,
+                        }));
+                    }
+                }
+            }
             libp2p::kad::Event::RoutablePeer { peer, address } => {
+                self.add_known_peer(peer);
                 self.events.push_back(Event::PeerSeen(PeerSeen {
                     peer_id: peer,
                     address,
@@ -132,6 +666,34 @@ impl Transport {
     }
 }
 
+fn status_error_reason(error: &request_response::OutboundFailure) -> &'static str {
+    match error {
+        request_response::OutboundFailure::DialFailure => "dial_failure",
+        request_response::OutboundFailure::Timeout => "timeout",
+        request_response::OutboundFailure::ConnectionClosed => "connection_closed",
+        request_response::OutboundFailure::UnsupportedProtocols => "unsupported",
+        request_response::OutboundFailure::Io(_) => "io",
+    }
+}
+
+fn dial_error_reason(error: &DialError) -> &'static str {
+    match error {
+        DialError::LocalPeerId { .. } => "local_peer_id",
+        DialError::NoAddresses => "no_addresses",
+        DialError::DialPeerConditionFalse(_) => "condition_false",
+        DialError::Aborted => "aborted",
+        DialError::WrongPeerId { .. } => "wrong_peer_id",
+        DialError::Denied { .. } => "denied",
+        DialError::Transport(_) => "transport",
+    }
+}
+
+fn delayed_interval(period: Duration) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+}
+
 impl futures::Stream for Transport {
     type Item = Event;
 
@@ -139,7 +701,7 @@ impl futures::Stream for Transport {
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        self.poll_event(cx).map(Some)
+        self.poll_allowed_event(cx).map(Some)
     }
 }
 
@@ -154,10 +716,26 @@ struct Behaviour {
     ping: libp2p::ping::Behaviour,
     identify: libp2p::identify::Behaviour,
     kademlia: libp2p::kad::Behaviour<libp2p::kad::store::MemoryStore>,
+    gossipsub: gossipsub::Behaviour,
+    status: request_response::Behaviour<StatusCodec>,
+    autonat: libp2p::autonat::Behaviour,
+    relay_client: libp2p::relay::client::Behaviour,
+    dcutr: libp2p::dcutr::Behaviour,
+    limits: libp2p::connection_limits::Behaviour,
+    blocked: allow_block_list::Behaviour<BlockedPeers>,
+    allowed: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
 }
 
 impl Behaviour {
-    fn new(key: &Keypair, network: Network) -> Self {
+    fn new(
+        key: &Keypair,
+        relay_client: libp2p::relay::client::Behaviour,
+        limits: ConnectionLimits,
+        allowed_peers: Option<HashSet<PeerId>>,
+        network: Network,
+        status_protocol: StreamProtocol,
+        status_timeout: Duration,
+    ) -> Self {
         let local_peer_id = PeerId::from(key.public());
 
         let agent_info: AgentInfo = get_agent_info!();
@@ -165,6 +743,18 @@ impl Behaviour {
             libp2p::identify::Config::new(protocol::ID_PROTOCOL.to_owned(), key.public())
                 .with_agent_version(agent_info.to_string());
 
+        // Strict validation drops unsigned messages, so heartbeats can be attributed to their author
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .validation_mode(gossipsub::ValidationMode::Strict)
+            .max_transmit_size(GOSSIPSUB_MAX_MESSAGE_SIZE)
+            .build()
+            .expect("Gossipsub config should be valid");
+        let gossipsub = gossipsub::Behaviour::new(
+            gossipsub::MessageAuthenticity::Signed(key.clone()),
+            gossipsub_config,
+        )
+        .expect("Gossipsub behaviour should be created");
+
         Self {
             ping: Default::default(),
             identify: libp2p::identify::Behaviour::new(identify_config),
@@ -173,6 +763,28 @@ impl Behaviour {
                 libp2p::kad::store::MemoryStore::new(local_peer_id),
                 libp2p::kad::Config::new(dht_protocol(network)),
             ),
+            gossipsub,
+            status: request_response::Behaviour::with_codec(
+                StatusCodec::default(),
+                [(status_protocol, request_response::ProtocolSupport::Outbound)],
+                request_response::Config::default().with_request_timeout(status_timeout),
+            ),
+            // Confirms the reachable external addresses, so --p2p-public-addrs isn't needed behind NAT
+            autonat: libp2p::autonat::Behaviour::new(local_peer_id, Default::default()),
+            // Workers only reachable through a relay are dialed over it, then upgraded by hole punching
+            relay_client,
+            dcutr: libp2p::dcutr::Behaviour::new(local_peer_id),
+            limits: libp2p::connection_limits::Behaviour::new(limits),
+            blocked: Default::default(),
+            allowed: allowed_peers
+                .map(|peers| {
+                    let mut allowed = allow_block_list::Behaviour::<AllowedPeers>::default();
+                    for peer_id in peers {
+                        allowed.allow_peer(peer_id);
+                    }
+                    allowed
+                })
+                .into(),
         }
     }
 }
@@ -0,0 +1,199 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A period during which the worker was continuously reachable
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct Interval {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WindowUptime {
+    pub window: String,
+    pub window_secs: u64,
+    pub uptime_ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WorkerUptime {
+    pub peer_id: String,
+    pub windows: Vec<WindowUptime>,
+    pub intervals: Vec<Interval>,
+}
+
+/// Tracks online intervals of workers from the arrival times of their pings.
+/// Two consecutive pings further apart than `max_gap` split the interval.
+pub struct UptimeTracker {
+    max_gap: i64,
+    windows: Vec<Duration>,
+    started_at: i64,
+    workers: HashMap<String, VecDeque<Interval>>,
+}
+
+impl UptimeTracker {
+    pub fn new(max_gap: Duration, windows: Vec<Duration>, started_at: i64) -> Self {
+        Self {
+            max_gap: max_gap.as_secs() as i64,
+            windows,
+            started_at,
+            workers: Default::default(),
+        }
+    }
+
+    pub fn record(&mut self, peer_id: &str, timestamp: i64) {
+        let retention = self.retention();
+        let intervals = self.workers.entry(peer_id.to_owned()).or_default();
+        match intervals.back_mut() {
+            Some(last) if timestamp - last.end <= self.max_gap => {
+                last.end = last.end.max(timestamp);
+            }
+            _ => intervals.push_back(Interval {
+                start: timestamp,
+                end: timestamp,
+            }),
+        }
+        while intervals
+            .front()
+            .is_some_and(|interval| interval.end < timestamp - retention)
+        {
+            intervals.pop_front();
+        }
+    }
+
+    pub fn remove(&mut self, peer_id: &str) {
+        self.workers.remove(peer_id);
+    }
+
+    pub fn worker_uptime(&self, peer_id: &str, now: i64) -> Option<WorkerUptime> {
+        let intervals = self.workers.get(peer_id)?;
+        Some(WorkerUptime {
+            peer_id: peer_id.to_owned(),
+            windows: self.window_ratios(intervals, now),
+            intervals: intervals.iter().copied().collect(),
+        })
+    }
+
+    pub fn all_ratios(&self, now: i64) -> Vec<(String, Vec<WindowUptime>)> {
+        self.workers
+            .iter()
+            .map(|(peer_id, intervals)| (peer_id.clone(), self.window_ratios(intervals, now)))
+            .collect()
+    }
+
+    fn window_ratios(&self, intervals: &VecDeque<Interval>, now: i64) -> Vec<WindowUptime> {
+        self.windows
+            .iter()
+            .map(|window| WindowUptime {
+                window: window_label(*window),
+                window_secs: window.as_secs(),
+                uptime_ratio: self.ratio(intervals, *window, now),
+            })
+            .collect()
+    }
+
+    fn ratio(&self, intervals: &VecDeque<Interval>, window: Duration, now: i64) -> f64 {
+        // Don't count the time before the observer started as downtime
+        let window_start = (now - window.as_secs() as i64).max(self.started_at);
+        if now <= window_start {
+            return 0.0;
+        }
+        let online: i64 = intervals
+            .iter()
+            .map(|interval| {
+                // A single ping proves the worker was online until the next expected one
+                let end = (interval.end + self.max_gap).min(now);
+                (end - interval.start.max(window_start)).max(0)
+            })
+            .sum();
+        (online as f64 / (now - window_start) as f64).min(1.0)
+    }
+
+    fn retention(&self) -> i64 {
+        self.windows
+            .iter()
+            .max()
+            .map_or(0, |window| window.as_secs() as i64)
+    }
+}
+
+/// Formats a window duration as a short label like `1h` or `7d`
+pub fn window_label(window: Duration) -> String {
+    let secs = window.as_secs();
+    match secs {
+        s if s > 0 && s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s > 0 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s > 0 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> UptimeTracker {
+        UptimeTracker::new(Duration::from_secs(60), vec![Duration::from_secs(3600)], 0)
+    }
+
+    fn intervals(tracker: &UptimeTracker, now: i64) -> Vec<(i64, i64)> {
+        tracker
+            .worker_uptime("peer", now)
+            .unwrap()
+            .intervals
+            .iter()
+            .map(|interval| (interval.start, interval.end))
+            .collect()
+    }
+
+    #[test]
+    fn pings_within_gap_are_merged() {
+        let mut tracker = tracker();
+        for timestamp in [100, 150, 210] {
+            tracker.record("peer", timestamp);
+        }
+        assert_eq!(intervals(&tracker, 210), [(100, 210)]);
+    }
+
+    #[test]
+    fn long_gap_splits_interval() {
+        let mut tracker = tracker();
+        for timestamp in [100, 150, 300, 330] {
+            tracker.record("peer", timestamp);
+        }
+        assert_eq!(intervals(&tracker, 330), [(100, 150), (300, 330)]);
+    }
+
+    #[test]
+    fn intervals_older_than_longest_window_are_dropped() {
+        let mut tracker = tracker();
+        tracker.record("peer", 100);
+        tracker.record("peer", 5000);
+        assert_eq!(intervals(&tracker, 5000), [(5000, 5000)]);
+    }
+
+    #[test]
+    fn ratio_counts_expected_gap_after_each_ping() {
+        let mut tracker = tracker();
+        tracker.record("peer", 0);
+        tracker.record("peer", 1800);
+        let windows = tracker.worker_uptime("peer", 3600).unwrap().windows;
+        // Online for 60s after each ping out of the hour
+        assert!((windows[0].uptime_ratio - 120.0 / 3600.0).abs() < 1e-9);
+        assert_eq!(windows[0].window, "1h");
+    }
+
+    #[test]
+    fn window_labels() {
+        assert_eq!(window_label(Duration::from_secs(7 * 86400)), "7d");
+        assert_eq!(window_label(Duration::from_secs(7200)), "2h");
+        assert_eq!(window_label(Duration::from_secs(300)), "5m");
+        assert_eq!(window_label(Duration::from_secs(90)), "90s");
+        assert_eq!(window_label(Duration::ZERO), "0s");
+    }
+}